@@ -0,0 +1,51 @@
+//! Embed the build information, read by `src/build_info.rs`
+//!
+//! `UPDATER_GIT_COMMIT` and `SOURCE_DATE_EPOCH` override the commit and the
+//! date, for the builds without the git repository or reproducible.
+use std::{env, path::Path, process::Command};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+fn main() {
+    // a missing path reruns the script on every build, like without `.git`
+    for path in [".git/HEAD", ".git/refs", ".git/packed-refs"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    println!("cargo:rerun-if-env-changed=UPDATER_GIT_COMMIT");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let commit = env::var("UPDATER_GIT_COMMIT").ok().or_else(|| {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()?;
+        output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    });
+    println!(
+        "cargo:rustc-env=UPDATER_GIT_COMMIT={}",
+        commit.unwrap_or_else(|| "unknown".to_owned())
+    );
+
+    let date = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .and_then(|epoch| DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(Utc::now);
+    println!(
+        "cargo:rustc-env=UPDATER_BUILD_DATE={}",
+        date.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+
+    let mut features = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_owned))
+        .filter(|feature| feature != "DEFAULT")
+        .map(|feature| feature.to_lowercase().replace('_', "-"))
+        .collect::<Vec<String>>();
+    features.sort();
+    println!("cargo:rustc-env=UPDATER_FEATURES={}", features.join(","));
+}
@@ -0,0 +1,60 @@
+//! # Build information
+//!
+//! The version, the git commit, the date and the features of the build, set
+//! at compile time by `build.rs`.
+//!
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const COMMIT: &str = env!("UPDATER_GIT_COMMIT");
+pub const BUILD_DATE: &str = env!("UPDATER_BUILD_DATE");
+const FEATURES: &str = env!("UPDATER_FEATURES");
+
+/// Version of `--version`, with the commit and the date
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    " (",
+    env!("UPDATER_GIT_COMMIT"),
+    " ",
+    env!("UPDATER_BUILD_DATE"),
+    ")"
+);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub commit: &'static str,
+    #[serde(rename = "buildDate")]
+    pub build_date: &'static str,
+    /// Enabled cargo features, like `otel`
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    pub fn current() -> Self {
+        Self {
+            version: VERSION,
+            commit: COMMIT,
+            build_date: BUILD_DATE,
+            features: FEATURES
+                .split(',')
+                .filter(|feature| !feature.is_empty())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_info() {
+        let build = BuildInfo::current();
+        assert_eq!(build.version, env!("CARGO_PKG_VERSION"));
+        assert!(!build.commit.is_empty());
+        assert!(chrono::DateTime::parse_from_rfc3339(build.build_date).is_ok());
+        assert_eq!(build.features.contains(&"otel"), cfg!(feature = "otel"));
+        assert!(LONG_VERSION.starts_with(VERSION));
+    }
+}
@@ -0,0 +1,249 @@
+//! # Command line
+//!
+//! Without a command (or with `serve`) the binary runs the api server. The
+//! other commands run the logic of the api once, with the same configuration
+//! and docker daemon, and print the response as json:
+//!
+//! ```bash
+//! updater update --image shop/api --tag 2.0
+//! updater rollback --service shop_api
+//! updater list --stack shop
+//! updater history --service shop_api
+//! updater config check
+//! ```
+//!
+//! The `client` command calls a remote updater instead, see [client](crate::client).
+//!
+//! The commands exit with `1` when the api would answer with an error.
+//!
+//! Protected services are not updated by the command line: their changes need
+//! an approval, only kept by the api server. `update` fails before updating
+//! any service when one of them is protected, and `rollback` refuses them
+//! like the api does.
+//!
+use std::{process::ExitCode, sync::Arc};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use clap::{Parser, Subcommand};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+
+use crate::{
+    client::ClientCommand,
+    config::Config,
+    controllers::{auth::Token, history, rollback, services, types::APIError, update},
+    services::background::Progress,
+    AppState,
+};
+
+/// Name of the actor of the changes done by the command line
+const ACTOR: &str = "cli";
+
+#[derive(Debug, Parser)]
+#[command(version, long_version = crate::build_info::LONG_VERSION, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the api server (default)
+    Serve,
+    /// Update the services using the image to a new tag
+    Update {
+        #[arg(long)]
+        image: String,
+        #[arg(long)]
+        tag: String,
+        /// Only update this service
+        #[arg(long)]
+        service: Option<String>,
+    },
+    /// Rollback a service to its previous spec
+    Rollback {
+        #[arg(long)]
+        service: String,
+    },
+    /// List the services
+    List {
+        /// Image name, without tag
+        #[arg(long)]
+        image: Option<String>,
+        /// Stack namespace
+        #[arg(long)]
+        stack: Option<String>,
+        /// Label key, or `key=value`
+        #[arg(long)]
+        label: Option<String>,
+    },
+    /// Show the audit history, the newest first
+    History {
+        /// Only the changes of this service
+        #[arg(long)]
+        service: Option<String>,
+        /// Only the changes not done by the updater
+        #[arg(long)]
+        external: bool,
+    },
+    /// Run the command on a remote updater, waiting for its job
+    Client {
+        /// Url of the updater
+        #[arg(long, env = "UPDATER_URL")]
+        url: String,
+        /// Token of the updater
+        #[arg(long, env = "UPDATER_TOKEN", hide_env_values = true)]
+        token: String,
+        /// Time (in seconds) to wait for the job
+        #[arg(long, env = "UPDATER_TIMEOUT", default_value_t = 1800)]
+        timeout: u64,
+        #[command(subcommand)]
+        command: ClientCommand,
+    },
+    /// Manage the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Check the configuration files and environment
+    Check,
+}
+
+/// Load and validate the configuration, without exiting on errors
+pub fn config_check() -> ExitCode {
+    match Config::extract() {
+        Ok(_) => {
+            println!("Configuration is valid");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Invalid configuration:\n{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run a command (other than `serve`, `client` and `config`) like the api would
+pub async fn run(command: Command, state: Arc<AppState>) -> ExitCode {
+    let token = Token::local(ACTOR);
+    let code = match command {
+        Command::Update {
+            image,
+            tag,
+            service,
+        } => {
+            let request = request(json!({ "image": image, "tag": tag, "service": service }));
+            match update::protected_services(&state, &request).await {
+                Ok(protected) if !protected.is_empty() => {
+                    print::<()>(Err(protected_error(protected)))
+                }
+                Ok(_) => print(update::update(&state, &token, &request, &Progress::none()).await),
+                Err(e) => print::<()>(Err(e)),
+            }
+        }
+        Command::Rollback { service } => {
+            let request = request(json!({ "service": service }));
+            print(rollback::rollback(&state, &token, &request, &Progress::none()).await)
+        }
+        Command::List {
+            image,
+            stack,
+            label,
+        } => {
+            let query = request(json!({ "image": image, "stack": stack, "label": label }));
+            print(
+                services::list_services(State(state.clone()), token, Query(query))
+                    .await
+                    .map(|Json(response)| response),
+            )
+        }
+        Command::History { service, external } => {
+            let query = request(json!({ "service": service, "external": external }));
+            print(
+                history::list_history(State(state.clone()), token, Query(query))
+                    .await
+                    .map(|Json(response)| response),
+            )
+        }
+        Command::Serve | Command::Client { .. } | Command::Config { .. } => {
+            unreachable!("serve, client and config are handled by main")
+        }
+    };
+    state.settings().notifiers.flush().await;
+    code
+}
+
+/// The error of an update of protected services, which the command line can't
+/// keep waiting for an approval
+fn protected_error(services: Vec<String>) -> APIError {
+    let mut error = APIError::new(
+        StatusCode::CONFLICT,
+        "protected_service",
+        &format!(
+            "Protected services need an approval, request the update from the api server: {}",
+            services.join(", ")
+        ),
+    );
+    error.args = services;
+    error
+}
+
+/// Build the request of the api from the arguments
+fn request<T: DeserializeOwned>(value: serde_json::Value) -> T {
+    serde_json::from_value(value).expect("arguments match the request")
+}
+
+fn print<T: Serialize>(result: Result<T, APIError>) -> ExitCode {
+    match result {
+        Ok(response) => {
+            println!("{}", serde_json::to_string_pretty(&response).unwrap());
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!(
+                "{}",
+                serde_json::to_string_pretty(&error.to_value()).unwrap()
+            );
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+        let cli = Cli::parse_from(["updater", "update", "--image", "shop/api", "--tag", "2.0"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Update { image, tag, service: None }) if image == "shop/api" && tag == "2.0"
+        ));
+        let cli = Cli::parse_from(["updater", "config", "check"]);
+        assert!(matches!(
+            cli.command,
+            Some(Command::Config {
+                command: ConfigCommand::Check
+            })
+        ));
+        assert!(Cli::parse_from(["updater"]).command.is_none());
+    }
+
+    #[test]
+    fn test_protected_error() {
+        let error = protected_error(vec!["shop_api".into()]);
+        assert_eq!(error.status, StatusCode::CONFLICT);
+        assert!(error.message.ends_with("shop_api"));
+    }
+}
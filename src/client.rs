@@ -0,0 +1,325 @@
+//! # Client of a remote updater
+//!
+//! The `client` command runs the updates and rollbacks on a remote updater,
+//! without access to the docker daemon: the request starts a background job,
+//! whose events are printed until it finishes.
+//!
+//! ```bash
+//! export UPDATER_URL=https://updater.usign.io UPDATER_TOKEN=secret
+//! updater client update --image shop/api --tag 2.0
+//! updater client rollback --service shop_api
+//! updater client wait 5b4f...
+//! ```
+//!
+//! The response of the job is printed as json, the command exits with `1`
+//! when the job fails, or when it is not finished after `--timeout` seconds
+//! (`UPDATER_TIMEOUT`, default 30 minutes): the job keeps running on the
+//! updater, it can be followed again with `wait`.
+//!
+use std::{process::ExitCode, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use clap::Subcommand;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::services::background::{BackgroundJob, JobEvent, JobState};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Subcommand)]
+pub enum ClientCommand {
+    /// Update the services using the image to a new tag
+    Update {
+        #[arg(long)]
+        image: String,
+        #[arg(long)]
+        tag: String,
+        /// Only update this service
+        #[arg(long)]
+        service: Option<String>,
+    },
+    /// Rollback a service to its previous spec
+    Rollback {
+        #[arg(long)]
+        service: String,
+    },
+    /// Wait for a job already started
+    Wait { id: Uuid },
+}
+
+/// Http client of the api of an updater
+pub struct Client {
+    url: String,
+    token: String,
+    timeout: Duration,
+    http: reqwest::Client,
+}
+
+impl Client {
+    pub fn new(url: &str, token: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_owned(),
+            token: token.to_owned(),
+            timeout: Duration::from_secs(1800),
+            http: reqwest::Client::builder()
+                .connect_timeout(CONNECT_TIMEOUT)
+                .build()
+                .expect("a client without custom tls"),
+        }
+    }
+
+    /// Time to wait for a job to finish
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run the command, printing the events of its job
+    pub async fn run(&self, command: ClientCommand) -> ExitCode {
+        let job = match command {
+            ClientCommand::Update {
+                image,
+                tag,
+                service,
+            } => {
+                let request = json!({ "image": image, "tag": tag, "service": service });
+                self.start("/update", request).await
+            }
+            ClientCommand::Rollback { service } => {
+                self.start("/rollback", json!({ "service": service })).await
+            }
+            ClientCommand::Wait { id } => Ok(id),
+        };
+        let job = match job {
+            Ok(id) => self.wait(&id, print_event).await,
+            Err(e) => Err(e),
+        };
+        match job {
+            Ok(job) => {
+                let result = job.result.unwrap_or_default();
+                println!("{}", serde_json::to_string_pretty(&result).unwrap());
+                if job.state == JobState::Succeeded {
+                    ExitCode::SUCCESS
+                } else {
+                    ExitCode::FAILURE
+                }
+            }
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                ExitCode::FAILURE
+            }
+        }
+    }
+
+    /// Start the request as a background job, returning the id of the job
+    pub async fn start(&self, path: &str, mut request: Value) -> anyhow::Result<Uuid> {
+        request["background"] = Value::Bool(true);
+        let response = self
+            .http
+            .post(format!("{}{}", self.url, path))
+            .bearer_auth(&self.token)
+            .json(&request)
+            .send()
+            .await
+            .with_context(|| format!("Error calling {}", self.url))?;
+        let job: BackgroundJob = serde_json::from_value(response_data(response).await?)
+            .context("Invalid job in the response")?;
+        Ok(job.id)
+    }
+
+    /// Follow the events of the job until it finishes, returning the finished
+    /// job, or fail when it doesn't finish in time
+    pub async fn wait(
+        &self,
+        id: &Uuid,
+        on_event: impl FnMut(&JobEvent),
+    ) -> anyhow::Result<BackgroundJob> {
+        match tokio::time::timeout(self.timeout, self.follow(id, on_event)).await {
+            Ok(job) => job,
+            Err(_) => bail!(
+                "Job {} not finished after {}s, it is still running on the updater",
+                id,
+                self.timeout.as_secs()
+            ),
+        }
+    }
+
+    async fn follow(
+        &self,
+        id: &Uuid,
+        mut on_event: impl FnMut(&JobEvent),
+    ) -> anyhow::Result<BackgroundJob> {
+        let response = self
+            .http
+            .get(format!("{}/jobs/{}/events", self.url, id))
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(response_error(response).await);
+        }
+        let mut stream = response.bytes_stream();
+        let mut buffer = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            buffer.extend_from_slice(&chunk?);
+            while let Some(position) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line = buffer.drain(..=position).collect::<Vec<u8>>();
+                let event: JobEvent =
+                    serde_json::from_slice(&line).context("Invalid event of the job")?;
+                on_event(&event);
+            }
+        }
+        let response = self
+            .http
+            .get(format!("{}/jobs/{}", self.url, id))
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+        let job: BackgroundJob = serde_json::from_value(response_data(response).await?)
+            .context("Invalid job in the response")?;
+        if job.state == JobState::Running {
+            bail!("The events of job {} ended before the job", id);
+        }
+        Ok(job)
+    }
+}
+
+/// The `data` of a successful response
+async fn response_data(response: reqwest::Response) -> anyhow::Result<Value> {
+    if !response.status().is_success() {
+        return Err(response_error(response).await);
+    }
+    let mut body: Value = response.json().await.context("Invalid response")?;
+    Ok(body["data"].take())
+}
+
+/// The status and the message of a failed response
+async fn response_error(response: reqwest::Response) -> anyhow::Error {
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<Value>(&text)
+        .ok()
+        .and_then(|body| body["message"].as_str().map(str::to_owned))
+        .unwrap_or(text);
+    anyhow!("{}: {}", status, message)
+}
+
+fn print_event(event: &JobEvent) {
+    match &event.service {
+        Some(service) => eprintln!("{} {}: {}", event.time.to_rfc3339(), service, event.message),
+        None => eprintln!("{} {}", event.time.to_rfc3339(), event.message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::Path,
+        http::StatusCode,
+        routing::{get, post},
+        Json, Router,
+    };
+    use chrono::Utc;
+
+    fn job(id: Uuid, state: JobState) -> Value {
+        json!({
+            "id": id,
+            "kind": "update",
+            "actor": "github",
+            "state": state,
+            "createdAt": Utc::now(),
+            "finishedAt": null,
+            "events": [],
+            "result": { "message": "Service updated" },
+        })
+    }
+
+    /// Remote updater answering with a job of two events
+    async fn updater(id: Uuid) -> String {
+        let app = Router::new()
+            .route(
+                "/update",
+                post(move |Json(request): Json<Value>| async move {
+                    assert_eq!(request["background"], true);
+                    (
+                        StatusCode::ACCEPTED,
+                        Json(json!({ "code": "202", "data": job(id, JobState::Running) })),
+                    )
+                }),
+            )
+            .route(
+                "/jobs/:id/events",
+                get(|| async {
+                    let running = json!({ "time": Utc::now(), "state": "running", "service": "shop_api", "message": "Updating" });
+                    let finished = json!({ "time": Utc::now(), "state": "succeeded", "message": "Service updated" });
+                    format!("{}\n{}\n", running, finished)
+                }),
+            )
+            .route(
+                "/jobs/:id",
+                get(|Path(id): Path<Uuid>| async move {
+                    Json(json!({ "code": "200", "data": job(id, JobState::Succeeded) }))
+                }),
+            )
+            .route(
+                "/rollback",
+                post(|| async {
+                    (
+                        StatusCode::FORBIDDEN,
+                        Json(json!({ "code": "forbidden", "message": "Token ci does not have the update scope" })),
+                    )
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_client() {
+        let id = Uuid::new_v4();
+        let client = Client::new(&updater(id).await, "secret");
+        let started = client
+            .start("/update", json!({ "image": "shop/api", "tag": "2.0" }))
+            .await
+            .unwrap();
+        assert_eq!(started, id);
+        let mut events = vec![];
+        let job = client
+            .wait(&id, |event| events.push(event.clone()))
+            .await
+            .unwrap();
+        assert_eq!(job.state, JobState::Succeeded);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].service.as_deref(), Some("shop_api"));
+        assert!(events[1].is_final());
+
+        let error = client
+            .start("/rollback", json!({ "service": "shop_api" }))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("does not have the update scope"));
+    }
+
+    #[tokio::test]
+    async fn test_client_wait_timeout() {
+        // the events of the job never end
+        let app = Router::new().route(
+            "/jobs/:id/events",
+            get(|| async {
+                let events = futures::stream::pending::<Result<String, std::io::Error>>();
+                axum::body::Body::from_stream(events)
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let client = Client::new(&url, "secret").with_timeout(Duration::from_millis(100));
+        let error = client.wait(&Uuid::new_v4(), |_| {}).await.unwrap_err();
+        assert!(error.to_string().contains("not finished after"));
+    }
+}
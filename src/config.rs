@@ -1,28 +1,243 @@
-use std::{collections::HashMap, path::Path};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
 
 use figment::{
     providers::{Env, Format, Json, Serialized, Toml, Yaml},
-    Figment, Result,
+    value::Value,
+    Figment, Profile, Source,
 };
 use serde::{Deserialize, Serialize};
 
+use crate::controllers::auth::{SCOPE_APPROVER, SCOPE_LOGS, SCOPE_READ, SCOPE_UPDATE};
+use crate::services::hooks::Hook;
+use crate::services::notify::{
+    email::{EmailNotifier, SmtpSecurity},
+    error::NotifyError,
+    matrix::MatrixNotifier,
+    slack::SlackNotifier,
+    template::Template,
+    webhook::WebhookNotifier,
+    NotificationFilter, Notifier, Notifiers, Outcome,
+};
+use crate::services::redact::{Redactor, Secret};
+use crate::services::registry::{Registry, RegistryCredential};
+
+/// Prefix of the environment variables of the configuration
+const ENV_PREFIX: &str = "UPDATER_";
+const LOG_LEVELS: [&str; 5] = ["trace", "debug", "info", "warn", "error"];
+const LOG_FORMATS: [&str; 2] = ["text", "json"];
+const SCOPES: [&str; 4] = [SCOPE_UPDATE, SCOPE_APPROVER, SCOPE_READ, SCOPE_LOGS];
+const MIN_TOKEN_LENGTH: usize = 16;
+/// Room for the webhook payloads, like a GitHub push event
+const MIN_BODY_LIMIT: usize = 64 * 1024;
+/// Keys of the secrets, which can be given as references
+const SECRET_KEYS: [&str; 3] = ["token", "password", "access_token"];
+/// Maps whose values are all secrets
+const SECRET_MAPS: [&str; 2] = ["tokens", "headers"];
+
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct ConfigRegistry {
     pub name: String,
     pub url: String,
     pub username: String,
-    pub password: String,
+    pub password: Secret,
+}
+
+/// A token accepted by the api
+///
+/// A token can be configured as a plain secret, which grants the `update` scope,
+/// or as an object with the list of scopes it grants:
+/// * update: request service updates and releases
+/// * approver: list, approve or reject changes of protected services
+/// * read: read the state of the services and the pending changes
+/// * logs: read the logs of the services
+///
+///
+/// ```json
+/// "tokens": {
+///   "github": "secret",
+///   "release-manager": { "token": "other-secret", "scopes": ["update", "approver"] }
+/// }
+/// ```
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ConfigToken {
+    Secret(Secret),
+    Scoped { token: Secret, scopes: Vec<String> },
+}
+
+impl ConfigToken {
+    pub fn secret(&self) -> &str {
+        match self {
+            ConfigToken::Secret(token) => token.expose(),
+            ConfigToken::Scoped { token, .. } => token.expose(),
+        }
+    }
+
+    pub fn scopes(&self) -> Vec<String> {
+        match self {
+            ConfigToken::Secret(_) => vec![SCOPE_UPDATE.to_owned()],
+            ConfigToken::Scoped { scopes, .. } => scopes.clone(),
+        }
+    }
+}
+
+/// Kind of a notification sink
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ConfigNotificationSink {
+    /// Post the notification as json
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, Secret>,
+    },
+    /// Post a message to a Slack incoming webhook
+    Slack {
+        url: String,
+        channel: Option<String>,
+        username: Option<String>,
+    },
+    /// Send a message to a Matrix room
+    Matrix {
+        homeserver: String,
+        room_id: String,
+        access_token: Secret,
+    },
+    /// Send an email through a SMTP server, `security` is `starttls` (default),
+    /// `tls` or `none`
+    Email {
+        host: String,
+        port: Option<u16>,
+        #[serde(default)]
+        security: SmtpSecurity,
+        username: Option<String>,
+        password: Option<Secret>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+/// A notification sink, fired after each update, release and rollback
+///
+/// The filters are optional, an empty filter matches everything:
+/// * outcomes: `success`, `failure` or `pending` (waiting approval)
+/// * stacks: Stack namespaces of the services
+/// * images: Images (without tag) of the services
+///
+/// The message of each sink can be replaced by a `template`, see
+/// [Template](crate::services::notify::template) for the variables available.
+///
+/// ```json
+/// "notifications": [
+///   { "name": "team", "type": "slack", "url": "https://hooks.slack.com/services/...", "stacks": ["shop"], "template": "{{ actor }} deployed {% for change in changes %}{{ change.service }}:{{ change.new.tag }} {% endfor %}({{ outcome }})" },
+///   { "name": "ops", "type": "matrix", "homeserver": "https://matrix.org", "room_id": "!abc:matrix.org", "access_token": "secret", "outcomes": ["failure"] },
+///   { "name": "audit", "type": "webhook", "url": "https://audit.usign.io/deploys", "headers": { "X-Token": "secret" } },
+///   { "name": "stakeholders", "type": "email", "host": "smtp.usign.io", "port": 587, "username": "updater", "password": "secret", "from": "updater@usign.io", "to": ["cto@usign.io"] }
+/// ]
+/// ```
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct ConfigNotification {
+    pub name: String,
+    #[serde(flatten)]
+    pub sink: ConfigNotificationSink,
+    #[serde(default)]
+    pub outcomes: Vec<Outcome>,
+    #[serde(default)]
+    pub stacks: Vec<String>,
+    #[serde(default)]
+    pub images: Vec<String>,
+    pub template: Option<String>,
+}
+
+impl ConfigNotification {
+    pub fn filter(&self) -> NotificationFilter {
+        NotificationFilter {
+            outcomes: self.outcomes.clone(),
+            stacks: self.stacks.clone(),
+            images: self.images.clone(),
+        }
+    }
+
+    pub fn template(&self) -> Result<Option<Template>, NotifyError> {
+        self.template.as_deref().map(Template::new).transpose()
+    }
+
+    pub fn notifier(&self) -> Arc<dyn Notifier> {
+        match &self.sink {
+            ConfigNotificationSink::Webhook { url, headers } => {
+                let headers = headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), value.expose().to_owned()))
+                    .collect();
+                Arc::new(WebhookNotifier::new(url, headers))
+            }
+            ConfigNotificationSink::Slack {
+                url,
+                channel,
+                username,
+            } => Arc::new(SlackNotifier::new(url, channel.clone(), username.clone())),
+            ConfigNotificationSink::Matrix {
+                homeserver,
+                room_id,
+                access_token,
+            } => Arc::new(MatrixNotifier::new(
+                homeserver,
+                room_id,
+                access_token.expose(),
+            )),
+            ConfigNotificationSink::Email {
+                host,
+                port,
+                security,
+                username,
+                password,
+                from,
+                to,
+            } => {
+                let notifier = EmailNotifier::new(host, *port, *security, from, to);
+                match (username, password) {
+                    (Some(username), Some(password)) => {
+                        Arc::new(notifier.with_credentials(username, password.expose()))
+                    }
+                    _ => Arc::new(notifier),
+                }
+            }
+        }
+    }
 }
 
 /// # Configuration for the application
 ///
 /// Permit to configure the application with the following options:
-/// * log_level: The level of logging - default: debug
-/// * tokens: A list of tokens to be used for authentication
+/// * log_level: The level of logging (trace, debug, info, warn or error) - default: debug
+/// * log_format: `text`, or `json` with one object per line, see [request](crate::controllers::request) for the fields of the requests - default: text
+/// * tokens: A list of tokens to be used for authentication, each secret with at least 16 characters
 /// * port: The port to run the server on - default: 3000
 /// * host: The host to run the server on - default: 0.0.0.0
 /// * docker_url: The url to the docker daemon - default: http://localhost:8080
 /// * registries: A list of docker registries to authenticate with
+/// * protected_label: Services with this label set to `true` need an approval before update - default: updater.protected
+/// * canary_replicas: Default number of replicas of a canary service - default: 1
+/// * canary_healthy_period: Default time the canary must stay healthy - default: 30 seconds
+/// * canary_timeout: Time to wait for a healthy canary before aborting - default: 300 seconds
+/// * docker_events: Follow the docker events, caching the services and detecting external changes - default: true
+/// * services_cache_ttl: Max age of the services cache - default: 60 seconds
+/// * history_size: Number of entries of the audit history kept in memory - default: 1000
+/// * history_file: File where the audit history is appended (json lines) - default: none
+/// * notifications: A list of notification sinks, see [ConfigNotification]
+/// * hooks: A list of hooks run before and after the updates, see [Hook]
+/// * job_timeout: Default time to wait for a job run with `/jobs/run` - default: 600 seconds
+/// * prepull: Pull the new image on the nodes of the service before updating it - default: false
+/// * prepull_timeout: Time to wait for the image on every node - default: 300 seconds
+/// * reload_interval: Interval between the checks of the configuration files, `0` to only reload on `SIGHUP` - default: 5 seconds
+/// * otel_endpoint: Base url of the OTLP/HTTP collector receiving the traces, like `http://otel-collector:4318`, see [telemetry](crate::telemetry) - default: none
+/// * otel_service_name: Name of the service in the traces - default: updater
+/// * redact_env: Patterns of the environment variables whose values are hidden from the logs, the notifications and the responses, see [redact](crate::services::redact) - default: password, passwd, secret, token, key, credential
 ///
 /// You can defined the path for config files via env: `CONFIG_PATH`.
 /// The default path is the `cwd`.
@@ -33,41 +248,85 @@ pub struct ConfigRegistry {
 /// * config.yaml
 /// * Environment variables prefixed with UPDATER_
 ///
+/// Every invalid value is reported with the file or the environment variable
+/// it comes from, see [Config::extract].
+///
+/// ## Secrets
+///
+/// The tokens, the passwords, the `access_token` and the `headers` of the
+/// notifications can be read from a file (like a docker secret) with the
+/// `_file` suffix, or from a `file:` or `env:` reference:
+///
+/// ```json
+/// "tokens": { "github_file": "/run/secrets/github_token" },
+/// "registries": [
+///   { "name": "ghcr", "url": "https://ghcr.io", "username": "nsfilho", "password_file": "/run/secrets/registry_pw" }
+/// ],
+/// "notifications": [
+///   { "name": "ops", "type": "matrix", "homeserver": "https://matrix.org", "room_id": "!abc:matrix.org", "access_token": "env:MATRIX_TOKEN" }
+/// ]
+/// ```
+///
+/// The references are resolved at each load, a rotated secret file is read
+/// again on `SIGHUP`.
+///
+/// The server reloads the configuration when the files change or on `SIGHUP`,
+/// see [reload](crate::reload).
+///
 /// ## Example: config.json
 ///
 /// ```json
 /// {
 ///    "log_level": "info",
+///    "log_format": "text",
 ///    "tokens": {
-///      "github": "secret"
+///      "github": "a-long-random-secret"
 ///    },
 ///    "port": 3000,
 ///    "host": "0.0.0.0",
 ///    "docker_url": "http://localhost:8080",
+///    "registries": [
 ///         {
 ///             "name": "usign",
 ///             "url": "http://registry.usign.io",
 ///             "username": "servers",
 ///             "password": "secret"
 ///         }
-///    ]
+///    ],
 ///    "graceful_shutdown_timeout": 30,
-///    "http_body_limit": 1024,
-///    "http_request_timeout": 10
+///    "http_body_limit": 1048576,
+///    "http_request_timeout": 10,
+///    "protected_label": "updater.protected",
+///    "canary_replicas": 1,
+///    "canary_healthy_period": 30,
+///    "canary_timeout": 300,
+///    "docker_events": true,
+///    "services_cache_ttl": 60,
+///    "history_size": 1000,
+///    "history_file": "/var/lib/updater/history.jsonl",
+///    "notifications": [],
+///    "hooks": [],
+///    "job_timeout": 600,
+///    "prepull": false,
+///    "prepull_timeout": 300,
+///    "reload_interval": 5,
+///    "otel_endpoint": null,
+///    "otel_service_name": "updater",
+///    "redact_env": ["password", "passwd", "secret", "token", "key", "credential"]
 /// }
+/// ```
 ///
 /// ## Parameters
 ///
 /// * graceful_shutdown_timeout: The time to wait for a graceful shutdown - default: 30 seconds
-/// * http_body_limit: The maximum size of the request body - default: 1KB
+/// * http_body_limit: The maximum size of the request body, at least 64KB - default: 1MB
 /// * http_request_timeout: The timeout for a request - default: 10 seconds
 ///
-/// ```
-///
 #[derive(Debug, PartialEq, Deserialize, Serialize)]
 pub struct Config {
     pub log_level: String,
-    pub tokens: HashMap<String, String>,
+    pub log_format: String,
+    pub tokens: HashMap<String, ConfigToken>,
     pub port: u16,
     pub host: String,
     pub docker_url: String,
@@ -75,43 +334,287 @@ pub struct Config {
     pub graceful_shutdown_timeout: u64,
     pub http_body_limit: usize,
     pub http_request_timeout: u64,
+    pub protected_label: String,
+    pub canary_replicas: u64,
+    pub canary_healthy_period: u64,
+    pub canary_timeout: u64,
+    pub docker_events: bool,
+    pub services_cache_ttl: u64,
+    pub history_size: usize,
+    pub history_file: Option<String>,
+    pub notifications: Vec<ConfigNotification>,
+    pub hooks: Vec<Hook>,
+    pub job_timeout: u64,
+    pub prepull: bool,
+    pub prepull_timeout: u64,
+    pub reload_interval: u64,
+    pub otel_endpoint: Option<String>,
+    pub otel_service_name: String,
+    pub redact_env: Vec<String>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             log_level: "debug".to_owned(),
+            log_format: "text".to_owned(),
             tokens: HashMap::new(),
             port: 3000,
             host: "0.0.0.0".to_owned(),
             docker_url: "http://localhost:8080".to_owned(),
             registries: vec![],
             graceful_shutdown_timeout: 30,
-            http_body_limit: 1024,
+            http_body_limit: 1024 * 1024,
             http_request_timeout: 10,
+            protected_label: "updater.protected".to_owned(),
+            canary_replicas: 1,
+            canary_healthy_period: 30,
+            canary_timeout: 300,
+            docker_events: true,
+            services_cache_ttl: 60,
+            history_size: 1000,
+            history_file: None,
+            notifications: vec![],
+            hooks: vec![],
+            job_timeout: 600,
+            prepull: false,
+            prepull_timeout: 300,
+            reload_interval: 5,
+            otel_endpoint: None,
+            otel_service_name: "updater".to_owned(),
+            redact_env: ["password", "passwd", "secret", "token", "key", "credential"]
+                .map(str::to_owned)
+                .to_vec(),
         }
     }
 }
 
 impl Config {
     pub fn load() -> Self {
-        let config_path = std::env::var("CONFIG_PATH").unwrap_or_else(|_| "./".to_owned());
-        let config_path = Path::new(&config_path);
-        let config: Result<Config> = Figment::from(Serialized::from(Config::default(), "default"))
-            .merge(Toml::file(config_path.join("config.toml")))
-            .merge(Json::file(config_path.join("config.json")))
-            .merge(Yaml::file(config_path.join("config.yaml")))
-            .merge(Env::prefixed("UPDATER_"))
-            .extract();
-        match config {
+        match Self::extract() {
             Ok(config) => config,
-            Err(e) => {
-                eprintln!("Error loading configuration: {}", e);
+            Err(errors) => {
+                eprintln!("Error loading configuration:\n{}", errors);
                 std::process::exit(1);
             }
         }
     }
 
+    /// The configuration files in `CONFIG_PATH`: toml, json and yaml
+    pub fn files() -> [PathBuf; 3] {
+        let config_path = std::env::var("CONFIG_PATH").unwrap_or_else(|_| "./".to_owned());
+        let config_path = Path::new(&config_path);
+        ["config.toml", "config.json", "config.yaml"].map(|file| config_path.join(file))
+    }
+
+    /// Modification time of each configuration file, `None` when missing
+    pub fn modified() -> Vec<Option<SystemTime>> {
+        Self::files()
+            .iter()
+            .map(|file| {
+                file.metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Read and validate the configuration files and environment
+    ///
+    /// All the problems are returned, each with the source of its value.
+    pub fn extract() -> Result<Self, ConfigErrors> {
+        let [toml, json, yaml] = Self::files();
+        let figment = Figment::from(Serialized::from(Config::default(), "default"))
+            .merge(Toml::file(toml))
+            .merge(Json::file(json))
+            .merge(Yaml::file(yaml))
+            .merge(Env::prefixed(ENV_PREFIX));
+        let located = |errors: Vec<ConfigError>| {
+            ConfigErrors(
+                errors
+                    .into_iter()
+                    .map(|error| ConfigError {
+                        source: source(&figment, &error.key),
+                        ..error
+                    })
+                    .collect(),
+            )
+        };
+        let extract_errors = |error: figment::Error| {
+            located(
+                error
+                    .into_iter()
+                    .map(|error| ConfigError::new(&error.path.join("."), &error.kind.to_string()))
+                    .collect(),
+            )
+        };
+        let mut value: Value = figment.extract().map_err(extract_errors)?;
+        let mut errors = vec![];
+        resolve_secrets(&mut value, "", &mut errors);
+        if !errors.is_empty() {
+            return Err(located(errors));
+        }
+        let config: Config = Figment::from(Serialized::defaults(value))
+            .extract()
+            .map_err(extract_errors)?;
+        config.validate().map_err(located)?;
+        Ok(config)
+    }
+
+    /// Check the values the types cannot, returning every problem found
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = vec![];
+        let mut check = |valid: bool, key: &str, message: &str| {
+            if !valid {
+                errors.push(ConfigError::new(key, message));
+            }
+        };
+        check(
+            LOG_LEVELS.contains(&self.log_level.to_lowercase().as_str()),
+            "log_level",
+            &format!(
+                "unknown level {}, expected one of {}",
+                self.log_level,
+                LOG_LEVELS.join(", ")
+            ),
+        );
+        check(
+            LOG_FORMATS.contains(&self.log_format.as_str()),
+            "log_format",
+            &format!(
+                "unknown format {}, expected one of {}",
+                self.log_format,
+                LOG_FORMATS.join(", ")
+            ),
+        );
+        for (name, token) in &self.tokens {
+            let key = format!("tokens.{}", name);
+            check(!token.secret().is_empty(), &key, "empty secret");
+            check(
+                token.secret().is_empty() || token.secret().len() >= MIN_TOKEN_LENGTH,
+                &key,
+                &format!("secret shorter than {} characters", MIN_TOKEN_LENGTH),
+            );
+            for scope in token.scopes() {
+                check(
+                    SCOPES.contains(&scope.as_str()),
+                    &key,
+                    &format!(
+                        "unknown scope {}, expected one of {}",
+                        scope,
+                        SCOPES.join(", ")
+                    ),
+                );
+            }
+        }
+        check(
+            valid_http_url(&self.docker_url),
+            "docker_url",
+            &format!("{} is not an http(s) url", self.docker_url),
+        );
+        if let Some(endpoint) = &self.otel_endpoint {
+            check(
+                valid_http_url(endpoint),
+                "otel_endpoint",
+                &format!("{} is not an http(s) url", endpoint),
+            );
+        }
+        for (index, registry) in self.registries.iter().enumerate() {
+            check(
+                valid_http_url(&registry.url),
+                &format!("registries.{}.url", index),
+                &format!(
+                    "{} of registry {} is not an http(s) url",
+                    registry.url, registry.name
+                ),
+            );
+        }
+        check(
+            self.http_body_limit >= MIN_BODY_LIMIT,
+            "http_body_limit",
+            &format!(
+                "{} bytes can't hold a webhook payload, at least {} expected",
+                self.http_body_limit, MIN_BODY_LIMIT
+            ),
+        );
+        for (key, value) in [
+            ("http_request_timeout", self.http_request_timeout),
+            ("graceful_shutdown_timeout", self.graceful_shutdown_timeout),
+            ("canary_timeout", self.canary_timeout),
+            ("job_timeout", self.job_timeout),
+            ("prepull_timeout", self.prepull_timeout),
+        ] {
+            check(value > 0, key, "must be greater than 0 seconds");
+        }
+        check(
+            self.canary_healthy_period < self.canary_timeout,
+            "canary_healthy_period",
+            &format!(
+                "the canary can't stay healthy {}s before the canary_timeout of {}s",
+                self.canary_healthy_period, self.canary_timeout
+            ),
+        );
+        check(
+            self.canary_replicas > 0,
+            "canary_replicas",
+            "must be at least 1",
+        );
+        for (index, notification) in self.notifications.iter().enumerate() {
+            if let Err(e) = notification.template() {
+                check(
+                    false,
+                    &format!("notifications.{}.template", index),
+                    &format!("notification {}: {}", notification.name, e),
+                );
+            }
+        }
+        for (index, hook) in self.hooks.iter().enumerate() {
+            if let Err(e) = hook.validate() {
+                check(false, &format!("hooks.{}", index), &e);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Build the registry client with the credentials
+    pub fn registry(&self) -> Registry {
+        Registry::new(
+            self.registries
+                .iter()
+                .map(|registry| RegistryCredential {
+                    url: registry.url.clone(),
+                    username: registry.username.clone(),
+                    password: registry.password.clone(),
+                })
+                .collect(),
+        )
+    }
+
+    pub fn redactor(&self) -> Redactor {
+        Redactor::new(&self.redact_env)
+    }
+
+    /// Build the notification sinks
+    pub fn notifiers(&self) -> Notifiers {
+        self.notifications.iter().fold(
+            Notifiers::new().with_redactor(self.redactor()),
+            |notifiers, notification| {
+                // templates are checked by validate, an invalid one falls back to the default message
+                notifiers.with_sink(
+                    &notification.name,
+                    notification.filter(),
+                    notification.template().ok().flatten(),
+                    notification.notifier(),
+                )
+            },
+        )
+    }
+
+    /// The level of logging, checked by [validate](Config::validate)
     pub fn log_level(&self) -> tracing::Level {
         match self.log_level.to_lowercase().as_str() {
             "trace" => tracing::Level::TRACE,
@@ -123,3 +626,324 @@ impl Config {
         }
     }
 }
+
+/// A problem of the configuration
+#[derive(Debug, PartialEq)]
+pub struct ConfigError {
+    /// Path of the value, like `tokens.github`
+    pub key: String,
+    pub message: String,
+    /// File or environment variable of the value, when known
+    pub source: Option<String>,
+}
+
+impl ConfigError {
+    fn new(key: &str, message: &str) -> Self {
+        Self {
+            key: key.to_owned(),
+            message: message.to_owned(),
+            source: None,
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)?;
+        if let Some(source) = &self.source {
+            write!(f, " (in {})", source)?;
+        }
+        Ok(())
+    }
+}
+
+/// Every problem of the configuration, one per line
+#[derive(Debug, PartialEq)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl std::fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let lines = self.0.iter().map(|error| format!("* {}", error));
+        write!(f, "{}", lines.collect::<Vec<String>>().join("\n"))
+    }
+}
+
+/// Where the value of the key (or of its closest parent) comes from: a file,
+/// an environment variable or the defaults
+fn source(figment: &Figment, key: &str) -> Option<String> {
+    let mut keys = key.split('.').collect::<Vec<&str>>();
+    while !keys.is_empty() {
+        if let Some(metadata) = figment.find_metadata(&keys.join(".")) {
+            return Some(match &metadata.source {
+                Some(Source::File(path)) => path.display().to_string(),
+                Some(Source::Custom(source)) => source.clone(),
+                _ if metadata.name.contains("environment") => format!(
+                    "{}{}",
+                    ENV_PREFIX,
+                    metadata.interpolate(&Profile::Default, &keys[..1])
+                ),
+                _ => metadata.name.to_string(),
+            });
+        }
+        keys.pop();
+    }
+    None
+}
+
+/// Replace the references of the secrets by their value: a `{key}_file` next
+/// to the key, or a `file:` or `env:` prefix in the value
+fn resolve_secrets(value: &mut Value, path: &str, errors: &mut Vec<ConfigError>) {
+    let key_of = |name: &str| {
+        if path.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}.{}", path, name)
+        }
+    };
+    match value {
+        Value::Dict(_, dict) => {
+            let secret_map = SECRET_MAPS.contains(&path.rsplit('.').next().unwrap_or_default());
+            let is_secret = |name: &str| secret_map || SECRET_KEYS.contains(&name);
+            let files = dict
+                .keys()
+                .filter_map(|name| name.strip_suffix("_file"))
+                .filter(|name| is_secret(name))
+                .map(str::to_owned)
+                .collect::<Vec<String>>();
+            for name in files {
+                let file = dict.remove(&format!("{}_file", name));
+                if dict.contains_key(&name) {
+                    errors.push(ConfigError::new(
+                        &key_of(&name),
+                        &format!("both {} and {}_file are set", name, name),
+                    ));
+                    continue;
+                }
+                match file.as_ref().and_then(Value::as_str) {
+                    Some(file) => match read_secret_file(file) {
+                        Ok(secret) => {
+                            dict.insert(name, Value::from(secret));
+                        }
+                        Err(e) => errors.push(ConfigError::new(&key_of(&name), &e)),
+                    },
+                    None => errors.push(ConfigError::new(
+                        &key_of(&format!("{}_file", name)),
+                        "expected the path of a file",
+                    )),
+                }
+            }
+            for (name, value) in dict.iter_mut() {
+                let key = key_of(name);
+                match value {
+                    Value::String(_, reference) if is_secret(name) => {
+                        match resolve_reference(reference) {
+                            Ok(Some(secret)) => *value = Value::from(secret),
+                            Ok(None) => {}
+                            Err(e) => errors.push(ConfigError::new(&key, &e)),
+                        }
+                    }
+                    _ => resolve_secrets(value, &key, errors),
+                }
+            }
+        }
+        Value::Array(_, values) => {
+            for (index, value) in values.iter_mut().enumerate() {
+                resolve_secrets(value, &key_of(&index.to_string()), errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The value of a `file:` or `env:` reference, `None` for a plain secret
+fn resolve_reference(reference: &str) -> Result<Option<String>, String> {
+    if let Some(file) = reference.strip_prefix("file:") {
+        read_secret_file(file).map(Some)
+    } else if let Some(name) = reference.strip_prefix("env:") {
+        std::env::var(name)
+            .map(Some)
+            .map_err(|_| format!("environment variable {} is not set", name))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Content of a secret file, without the final line break
+fn read_secret_file(file: &str) -> Result<String, String> {
+    std::fs::read_to_string(file)
+        .map(|secret| secret.trim_end_matches(['\r', '\n']).to_owned())
+        .map_err(|e| format!("can't read the secret file {}: {}", file, e))
+}
+
+fn valid_http_url(url: &str) -> bool {
+    reqwest::Url::parse(url)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notifications() {
+        let notification: ConfigNotification = serde_json::from_value(serde_json::json!({
+            "name": "ops",
+            "type": "matrix",
+            "homeserver": "https://matrix.org",
+            "room_id": "!abc:matrix.org",
+            "access_token": "secret",
+            "outcomes": ["failure"]
+        }))
+        .unwrap();
+        assert_eq!(
+            notification.sink,
+            ConfigNotificationSink::Matrix {
+                homeserver: "https://matrix.org".into(),
+                room_id: "!abc:matrix.org".into(),
+                access_token: "secret".into(),
+            }
+        );
+        assert_eq!(notification.filter().outcomes, vec![Outcome::Failure]);
+    }
+
+    #[test]
+    fn test_validate_templates() {
+        let mut config = Config::default();
+        config.notifications.push(ConfigNotification {
+            name: "team".into(),
+            sink: ConfigNotificationSink::Slack {
+                url: "http://localhost".into(),
+                channel: None,
+                username: None,
+            },
+            outcomes: vec![],
+            stacks: vec![],
+            images: vec![],
+            template: Some("{{ actor }} {{ request.tag | default(value=\"\") }}".into()),
+        });
+        assert!(config.validate().is_ok());
+        // the releases and the rollbacks have no tag in their request
+        config.notifications[0].template = Some("{{ actor }} {{ request.tag }}".into());
+        assert!(config.validate().is_err());
+        config.notifications[0].template = Some("{% if outcome %}".into());
+        let errors = config.validate().unwrap_err();
+        assert_eq!(errors[0].key, "notifications.0.template");
+        assert!(errors[0]
+            .message
+            .starts_with("notification team: Template error"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = Config {
+            log_level: "verbose".into(),
+            log_format: "yaml".into(),
+            docker_url: "unix:///var/run/docker.sock".into(),
+            http_body_limit: 1024,
+            canary_healthy_period: 600,
+            ..Config::default()
+        };
+        config
+            .tokens
+            .insert("github".into(), ConfigToken::Secret("secret".into()));
+        config.tokens.insert(
+            "ops".into(),
+            ConfigToken::Scoped {
+                token: "a-long-random-secret".into(),
+                scopes: vec!["admin".into()],
+            },
+        );
+        let mut keys = config
+            .validate()
+            .unwrap_err()
+            .into_iter()
+            .map(|error| error.key)
+            .collect::<Vec<String>>();
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![
+                "canary_healthy_period",
+                "docker_url",
+                "http_body_limit",
+                "log_format",
+                "log_level",
+                "tokens.github",
+                "tokens.ops"
+            ]
+        );
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)] // the result of the jail is figment's
+    fn test_source() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file("config.toml", "log_level = \"verbose\"")?;
+            jail.set_env("CONFIG_PATH", jail.directory().display().to_string());
+            jail.set_env("UPDATER_HTTP_BODY_LIMIT", "1024");
+            let errors = Config::extract().unwrap_err().0;
+            assert_eq!(errors.len(), 2);
+            assert_eq!(errors[0].key, "log_level");
+            assert!(errors[0].source.as_ref().unwrap().ends_with("config.toml"));
+            assert_eq!(errors[1].key, "http_body_limit");
+            assert_eq!(errors[1].source.as_deref(), Some("UPDATER_HTTP_BODY_LIMIT"));
+            jail.set_env("UPDATER_PORT", "http");
+            let errors = Config::extract().unwrap_err().0;
+            assert_eq!(errors[0].key, "port");
+            assert_eq!(errors[0].source.as_deref(), Some("UPDATER_PORT"));
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[allow(clippy::result_large_err)] // the result of the jail is figment's
+    fn test_secrets() {
+        figment::Jail::expect_with(|jail| {
+            jail.create_file("registry_pw", "a-registry-password\n")?;
+            jail.create_file("github_token", "a-long-random-secret")?;
+            jail.create_file(
+                "config.json",
+                r#"{
+                    "tokens": {
+                        "github_file": "github_token",
+                        "ops": { "token": "env:OPS_TOKEN", "scopes": ["read"] }
+                    },
+                    "registries": [
+                        { "name": "ghcr", "url": "https://ghcr.io", "username": "nsfilho", "password_file": "registry_pw" }
+                    ],
+                    "protected_label": "env:NOT_A_SECRET"
+                }"#,
+            )?;
+            jail.set_env("CONFIG_PATH", jail.directory().display().to_string());
+            jail.set_env("OPS_TOKEN", "another-random-secret");
+            let config = Config::extract().unwrap();
+            assert_eq!(config.tokens["github"].secret(), "a-long-random-secret");
+            assert_eq!(config.tokens["ops"].secret(), "another-random-secret");
+            assert_eq!(
+                config.registries[0].password.expose(),
+                "a-registry-password"
+            );
+            assert_eq!(config.protected_label, "env:NOT_A_SECRET");
+            let debug = format!("{:?}", config);
+            assert!(!debug.contains("a-registry-password"));
+            assert!(!debug.contains("a-long-random-secret"));
+
+            jail.set_env("UPDATER_TOKENS", "{ops=\"file:missing\"}");
+            jail.set_env(
+                "UPDATER_REGISTRIES",
+                "[{password=\"x\",password_file=\"registry_pw\"}]",
+            );
+            let errors = Config::extract().unwrap_err().0;
+            assert_eq!(errors.len(), 2);
+            assert_eq!(errors[0].key, "registries.0.password");
+            assert_eq!(errors[0].message, "both password and password_file are set");
+            assert_eq!(errors[1].key, "tokens.ops");
+            assert!(errors[1]
+                .message
+                .starts_with("can't read the secret file missing"));
+            assert_eq!(errors[1].source.as_deref(), Some("UPDATER_TOKENS"));
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+use chrono::Utc;
+use serde::Serialize;
+use tracing::info;
+use uuid::Uuid;
+
+use super::{
+    auth::{Token, SCOPE_APPROVER, SCOPE_READ},
+    request::{detach, propagate, transaction},
+    types::APIError,
+    update::{before_update, restore_rollout_config},
+};
+use crate::{
+    services::{
+        approvals::PendingChange,
+        docker::types::{Service, ServiceResume},
+        history::{HistoryAction, HistoryEntry},
+        notify::{Notification, NotificationKind, ServiceChange},
+    },
+    AppState,
+};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct PendingChangesResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: Vec<PendingChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ApproveResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: ServiceResume,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct RejectResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: PendingChange,
+}
+
+/// List the changes waiting for approval
+pub async fn list_pending(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+) -> Result<Json<PendingChangesResponse>, APIError> {
+    token.require_any(&[SCOPE_READ, SCOPE_APPROVER])?;
+    let redactor = &state.settings().redactor;
+    let mut changes = state.approvals.list();
+    for change in changes.iter_mut() {
+        redactor.options(&mut change.options);
+    }
+    Ok(Json(PendingChangesResponse {
+        code: "200".to_string(),
+        transaction: transaction().to_string(),
+        message: "Pending changes".to_string(),
+        args: vec![],
+        data: changes,
+    }))
+}
+
+/// Approve a pending change, applying the stored update to the service
+///
+/// The change is kept pending when the update fails, to be approved again.
+pub async fn approve(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ApproveResponse>, APIError> {
+    token.require(SCOPE_APPROVER)?;
+    // taken while it is applied, so it is not approved twice
+    let change = state
+        .approvals
+        .take(&id)
+        .ok_or_else(|| APIError::not_found(&format!("Pending change {} not found", id)))?;
+    info!(
+        "Change {} approved by {}: {} -> {}:{}",
+        change.id, token.name, change.service_name, change.image, change.tag
+    );
+    let transaction = transaction();
+    let id = change.id;
+    // runs to its end (or restores the change) even when the request times out
+    let service = detach(async move {
+        match apply_change(&state, &token, &change, &transaction).await {
+            Ok(service) => Ok(service),
+            Err(e) => {
+                state.approvals.restore(change);
+                Err(e)
+            }
+        }
+    })
+    .await?;
+    Ok(Json(ApproveResponse {
+        code: "200".to_string(),
+        transaction: transaction.to_string(),
+        message: "Service updated".to_string(),
+        args: vec![id.to_string()],
+        data: service.into(),
+    }))
+}
+
+/// Update the service with the approved change
+async fn apply_change(
+    state: &Arc<AppState>,
+    token: &Token,
+    change: &PendingChange,
+    transaction: &Uuid,
+) -> Result<Service, APIError> {
+    let started_at = Utc::now();
+    let mut service = state.docker.service_inspect(&change.service_id).await?;
+    let from_image = service.spec.task_template.container_spec.image.clone();
+    let rollout = service.rollout_config();
+    service.apply_options(&change.options)?;
+    let service_change = ServiceChange::new(&service, &change.image, &change.tag);
+    let _expected = state
+        .history
+        .expect(&service.id, &format!("{}:{}", change.image, change.tag));
+    let result = match before_update(
+        state,
+        &service,
+        &change.image,
+        &change.tag,
+        None,
+        state.settings().config.prepull,
+    )
+    .await
+    {
+        Ok(()) => service.update_image(&change.image, &change.tag).await,
+        Err(e) => Err(e),
+    };
+    let notification = Notification::new(
+        NotificationKind::Update,
+        transaction,
+        &token.name,
+        started_at,
+    )
+    .with_request(change);
+    let entry = HistoryEntry::new(
+        HistoryAction::Approve,
+        &service.id,
+        &service.spec.name,
+        &token.name,
+    )
+    .with_images(
+        Some(&from_image),
+        Some(&service.spec.task_template.container_spec.image),
+    )
+    .with_transaction(transaction);
+    if let Err(e) = result {
+        state.history.record(entry.with_error(&e));
+        state.settings().notifiers.dispatch(
+            notification
+                .with_changes(vec![service_change.with_error(&e)])
+                .with_error(&e),
+        );
+        return Err(e.into());
+    }
+    state.history.record(entry);
+    if change.options.changes_rollout() {
+        tokio::spawn(propagate(restore_rollout_config(
+            state.clone(),
+            service.id.clone(),
+            rollout,
+        )));
+    }
+    state
+        .settings()
+        .hooks
+        .after(&state.docker, &service, &change.image, &change.tag)
+        .await;
+    state
+        .settings()
+        .notifiers
+        .dispatch(notification.with_changes(vec![service_change]));
+    Ok(service)
+}
+
+/// Reject a pending change, discarding it
+pub async fn reject(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(id): Path<Uuid>,
+) -> Result<Json<RejectResponse>, APIError> {
+    token.require(SCOPE_APPROVER)?;
+    let mut change = state
+        .approvals
+        .take(&id)
+        .ok_or_else(|| APIError::not_found(&format!("Pending change {} not found", id)))?;
+    info!(
+        "Change {} rejected by {}: {} -> {}:{}",
+        change.id, token.name, change.service_name, change.image, change.tag
+    );
+    state.history.record(
+        HistoryEntry::new(
+            HistoryAction::Reject,
+            &change.service_id,
+            &change.service_name,
+            &token.name,
+        )
+        .with_images(None, Some(&format!("{}:{}", change.image, change.tag))),
+    );
+    state.settings().redactor.options(&mut change.options);
+    Ok(Json(RejectResponse {
+        code: "200".to_string(),
+        transaction: transaction().to_string(),
+        message: "Change rejected".to_string(),
+        args: vec![change.id.to_string()],
+        data: change,
+    }))
+}
@@ -0,0 +1,87 @@
+use std::sync::Arc;
+
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+
+use super::{request::record_token, types::APIError};
+use crate::AppState;
+
+/// Scope needed to request service updates
+pub const SCOPE_UPDATE: &str = "update";
+/// Scope needed to approve or reject pending changes
+pub const SCOPE_APPROVER: &str = "approver";
+/// Scope needed to read the state of the services
+pub const SCOPE_READ: &str = "read";
+/// Scope needed to read the logs of the services
+pub const SCOPE_LOGS: &str = "logs";
+
+/// Token used to authenticate the request
+///
+/// It is extracted from the `Authorization: Bearer <token>` header and
+/// matched against the tokens in [Config](crate::config::Config).
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+impl Token {
+    /// Token of the command line, granting every scope: the user already has
+    /// access to the docker daemon
+    pub fn local(name: &str) -> Self {
+        Token {
+            name: name.to_owned(),
+            scopes: [SCOPE_UPDATE, SCOPE_APPROVER, SCOPE_READ, SCOPE_LOGS]
+                .map(str::to_owned)
+                .to_vec(),
+        }
+    }
+
+    /// Fail with `403` when the token does not grant the `scope`
+    pub fn require(&self, scope: &str) -> Result<(), APIError> {
+        self.require_any(&[scope])
+    }
+
+    /// Fail with `403` when the token grants none of the `scopes`
+    pub fn require_any(&self, scopes: &[&str]) -> Result<(), APIError> {
+        if self.scopes.iter().any(|s| scopes.contains(&s.as_str())) {
+            Ok(())
+        } else {
+            Err(APIError::forbidden(&format!(
+                "Token {} does not have the {} scope",
+                self.name,
+                scopes.join(" or ")
+            )))
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Token {
+    type Rejection = APIError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let secret = parts
+            .headers
+            .get(axum::http::header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| APIError::unauthorized("Missing bearer token"))?;
+        state
+            .settings()
+            .config
+            .tokens
+            .iter()
+            .find(|(_, token)| token.secret() == secret)
+            .map(|(name, token)| {
+                record_token(name);
+                Token {
+                    name: name.clone(),
+                    scopes: token.scopes(),
+                }
+            })
+            .ok_or_else(|| APIError::unauthorized("Invalid token"))
+    }
+}
@@ -1,14 +1,48 @@
-use axum::Json;
+//! # Echo and version
+//!
+//! `GET /` answers the name of the server, its time and the build of the
+//! updater. `GET /version` answers the build (version, git commit, date and
+//! features) with the version of the docker daemon, `null` until it is
+//! discovered (in background from the startup). Both endpoints don't need a token.
+//!
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use serde::Serialize;
 use serde_json::{json, Value};
-use uuid::Uuid;
 
-pub async fn get_root() -> Json<Value> {
-    let transaction = Uuid::new_v4();
+use super::request::transaction;
+use crate::{build_info::BuildInfo, services::docker::types::DockerVersion, AppState};
+
+/// Version of the docker daemon, as answered by `/` and `/version`
+#[derive(Debug, Serialize)]
+pub(crate) struct DockerInfo {
+    version: String,
+    #[serde(rename = "apiVersion")]
+    api_version: String,
+    os: String,
+    arch: String,
+}
+
+impl From<&DockerVersion> for DockerInfo {
+    fn from(version: &DockerVersion) -> Self {
+        Self {
+            version: version.version.clone(),
+            api_version: version.api_version.clone(),
+            os: version.os.clone(),
+            arch: version.arch.clone(),
+        }
+    }
+}
+
+pub async fn get_root(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let transaction = transaction();
     let host = hostname::get()
         .unwrap_or_else(|_| "unknown".into())
         .into_string()
         .unwrap_or_else(|_| "unknown".into());
-    let version = std::env::var("CARGO_PKG_VERSION").unwrap_or_else(|_| "unknown".into());
+    let build = BuildInfo::current();
+    let docker = state.docker_version().map(DockerInfo::from);
     let current_time = chrono::Utc::now();
     Json(json!({
         "code": "echo",
@@ -18,7 +52,84 @@ pub async fn get_root() -> Json<Value> {
         "data": {
             "server": host,
             "time": current_time.to_rfc3339(),
-            "version": version,
+            "version": build.version,
+            "build": build,
+            "docker": docker,
+        }
+    }))
+}
+
+pub async fn get_version(State(state): State<Arc<AppState>>) -> Json<Value> {
+    let build = BuildInfo::current();
+    let docker = state.docker_version().map(DockerInfo::from);
+    Json(json!({
+        "code": "version",
+        "transaction": transaction().to_string(),
+        "message": "OK",
+        "args": [],
+        "data": {
+            "version": build.version,
+            "commit": build.commit,
+            "buildDate": build.build_date,
+            "features": build.features,
+            "docker": docker,
         }
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::{http::StatusCode, routing::get, Router};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Docker daemon answering `/version` once `up` is set
+    async fn stand_in(up: Arc<AtomicBool>) -> String {
+        let app = Router::new().route(
+            "/version",
+            get(move || async move {
+                if !up.load(Ordering::SeqCst) {
+                    return Err(StatusCode::SERVICE_UNAVAILABLE);
+                }
+                Ok(Json(json!({
+                    "Version": "27.1.1",
+                    "ApiVersion": "1.46",
+                    "Os": "linux",
+                    "Arch": "amd64"
+                })))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_get_version() {
+        let up = Arc::new(AtomicBool::new(false));
+        let state = Arc::new(AppState::new(Config {
+            docker_url: stand_in(up.clone()).await,
+            ..Config::default()
+        }));
+
+        let Json(body) = get_version(State(state.clone())).await;
+        assert_eq!(body["data"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["data"]["commit"], crate::build_info::COMMIT);
+        assert!(body["data"]["docker"].is_null());
+
+        // the requests only read the version discovered in background
+        up.store(true, Ordering::SeqCst);
+        let Json(body) = get_version(State(state.clone())).await;
+        assert!(body["data"]["docker"].is_null());
+        state.discover_docker_version().await;
+        let Json(body) = get_version(State(state.clone())).await;
+        assert_eq!(body["data"]["docker"]["version"], "27.1.1");
+        assert_eq!(body["data"]["docker"]["apiVersion"], "1.46");
+
+        let Json(body) = get_root(State(state)).await;
+        assert_eq!(body["data"]["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(body["data"]["docker"]["os"], "linux");
+    }
+}
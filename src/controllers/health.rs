@@ -0,0 +1,230 @@
+//! # Health checks
+//!
+//! `GET /healthz` answers while the process runs (liveness). `GET /readyz`
+//! checks the dependencies (readiness) and answers `503` when one fails:
+//! * docker: the daemon answers its `/_ping`
+//! * swarm: the node is an active swarm manager
+//! * registries: each configured registry answers its api
+//!
+//! Both endpoints don't need a token.
+//!
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+
+use super::request::transaction;
+use crate::{
+    config::ConfigRegistry,
+    services::{docker::Docker, registry::Registry},
+    AppState,
+};
+
+/// Time to wait for each dependency
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+pub(crate) struct HealthResponse<T> {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: T,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Liveness {
+    status: DependencyStatus,
+}
+
+/// State of a dependency, with the error when it failed
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct DependencyStatus {
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl DependencyStatus {
+    fn ok() -> Self {
+        Self {
+            status: "ok".to_owned(),
+            message: None,
+        }
+    }
+
+    fn error(message: &str) -> Self {
+        Self {
+            status: "error".to_owned(),
+            message: Some(message.to_owned()),
+        }
+    }
+
+    fn is_ok(&self) -> bool {
+        self.message.is_none()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct Readiness {
+    docker: DependencyStatus,
+    swarm: DependencyStatus,
+    /// By name of the registry
+    registries: BTreeMap<String, DependencyStatus>,
+}
+
+impl Readiness {
+    fn is_ready(&self) -> bool {
+        self.docker.is_ok()
+            && self.swarm.is_ok()
+            && self.registries.values().all(DependencyStatus::is_ok)
+    }
+}
+
+/// The process is alive
+pub async fn healthz() -> Json<HealthResponse<Liveness>> {
+    Json(HealthResponse {
+        code: "200".to_string(),
+        transaction: transaction().to_string(),
+        message: "Alive".to_string(),
+        args: vec![],
+        data: Liveness {
+            status: DependencyStatus::ok(),
+        },
+    })
+}
+
+/// The dependencies are reachable
+pub async fn readyz(
+    State(state): State<Arc<AppState>>,
+) -> (StatusCode, Json<HealthResponse<Readiness>>) {
+    let settings = state.settings();
+    let readiness = readiness(
+        &state.docker,
+        &settings.registry,
+        &settings.config.registries,
+    )
+    .await;
+    let (status, message) = if readiness.is_ready() {
+        (StatusCode::OK, "Ready")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "Not ready")
+    };
+    (
+        status,
+        Json(HealthResponse {
+            code: status.as_u16().to_string(),
+            transaction: transaction().to_string(),
+            message: message.to_string(),
+            args: vec![],
+            data: readiness,
+        }),
+    )
+}
+
+/// Check the dependencies at the same time
+pub(crate) async fn readiness(
+    docker: &Docker,
+    registry: &Registry,
+    registries: &[ConfigRegistry],
+) -> Readiness {
+    let ping = check(async { docker.ping().await.map_err(|e| e.to_string()) });
+    let swarm = check(async {
+        let info = docker.info().await.map_err(|e| e.to_string())?;
+        let swarm = info.swarm;
+        if swarm.local_node_state != "active" {
+            return Err(format!(
+                "the node is not part of an active swarm (state {})",
+                swarm.local_node_state
+            ));
+        }
+        if !swarm.control_available {
+            return Err(format!("node {} is not a swarm manager", swarm.node_id));
+        }
+        Ok(())
+    });
+    let pings = futures::future::join_all(registries.iter().map(|config| async {
+        let status =
+            check(async { registry.ping(&config.url).await.map_err(|e| e.to_string()) }).await;
+        (config.name.clone(), status)
+    }));
+    let (docker, swarm, registries) = tokio::join!(ping, swarm, pings);
+    Readiness {
+        docker,
+        swarm,
+        registries: registries.into_iter().collect(),
+    }
+}
+
+async fn check(future: impl Future<Output = Result<(), String>>) -> DependencyStatus {
+    match tokio::time::timeout(CHECK_TIMEOUT, future).await {
+        Ok(Ok(())) => DependencyStatus::ok(),
+        Ok(Err(e)) => DependencyStatus::error(&e),
+        Err(_) => DependencyStatus::error(&format!(
+            "no answer after {} seconds",
+            CHECK_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, services::docker::DockerBuilder};
+    use axum::{http::StatusCode, routing::get, Router};
+    use serde_json::json;
+
+    /// Docker daemon and registry answering the checks
+    async fn stand_in(manager: bool) -> String {
+        let app = Router::new()
+            .route("/_ping", get(|| async { "OK" }))
+            .route(
+                "/info",
+                get(move || async move {
+                    Json(json!({
+                        "Swarm": { "NodeID": "n1", "LocalNodeState": "active", "ControlAvailable": manager }
+                    }))
+                }),
+            )
+            .route("/v2/", get(|| async { StatusCode::UNAUTHORIZED }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    fn registry(name: &str, url: &str) -> ConfigRegistry {
+        ConfigRegistry {
+            name: name.into(),
+            url: url.into(),
+            username: "updater".into(),
+            password: "secret".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_readiness() {
+        let url = stand_in(true).await;
+        let docker = DockerBuilder::builder().with_http_url(&url).build();
+        let config = Config {
+            registries: vec![registry("local", &url)],
+            ..Config::default()
+        };
+        let ready = readiness(&docker, &config.registry(), &config.registries).await;
+        assert!(ready.is_ready());
+        assert_eq!(ready.registries["local"], DependencyStatus::ok());
+
+        let worker = DockerBuilder::builder()
+            .with_http_url(&stand_in(false).await)
+            .build();
+        let registries = vec![registry("down", "http://127.0.0.1:1")];
+        let not_ready = readiness(&worker, &Registry::new(vec![]), &registries).await;
+        assert!(!not_ready.is_ready());
+        assert!(not_ready.docker.is_ok());
+        assert_eq!(
+            not_ready.swarm,
+            DependencyStatus::error("node n1 is not a swarm manager")
+        );
+        assert!(!not_ready.registries["down"].is_ok());
+    }
+}
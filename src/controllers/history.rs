@@ -0,0 +1,55 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    auth::{Token, SCOPE_READ},
+    request::transaction,
+    types::APIError,
+};
+use crate::{services::history::HistoryEntry, AppState};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct HistoryQuery {
+    /// Only the changes of this service (name or id)
+    service: Option<String>,
+    /// Only the changes not done by the updater
+    #[serde(default)]
+    external: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct HistoryResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: Vec<HistoryEntry>,
+}
+
+/// List the audit history, the newest first
+pub async fn list_history(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<HistoryResponse>, APIError> {
+    token.require(SCOPE_READ)?;
+    let entries = state
+        .history
+        .list(query.service.as_deref())
+        .into_iter()
+        .rev()
+        .filter(|entry| !query.external || entry.external)
+        .collect();
+    Ok(Json(HistoryResponse {
+        code: "200".to_string(),
+        transaction: transaction().to_string(),
+        message: "History".to_string(),
+        args: vec![],
+        data: entries,
+    }))
+}
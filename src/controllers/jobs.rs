@@ -0,0 +1,334 @@
+use std::{future::Future, sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use futures::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::info;
+use uuid::Uuid;
+
+use super::{
+    auth::{Token, SCOPE_APPROVER, SCOPE_READ, SCOPE_UPDATE},
+    request::{detach, propagate, transaction},
+    types::APIError,
+};
+use crate::{
+    services::{
+        background::{BackgroundJob, JobEvent},
+        history::{HistoryAction, HistoryEntry},
+        jobs::{self, JobResult},
+    },
+    AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RunJobRequest {
+    /// Service (name or id) the job is built from
+    service: String,
+    /// Image of the job, default to the image of the service
+    image: Option<String>,
+    /// Command of the job, default to the command of the image
+    command: Option<Vec<String>>,
+    /// Time (in seconds) to wait for the job, default from the config
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct RunJobResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: JobResult,
+}
+
+/// Run a one-off job with the spec of a service, returning its exit code and logs
+///
+/// The jobs of a protected service need the `approver` scope too. The job
+/// keeps running when the request times out: it is recorded in the
+/// history and its service is removed once it finishes.
+pub async fn run_job(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Json(payload): Json<RunJobRequest>,
+) -> Result<Json<RunJobResponse>, APIError> {
+    token.require(SCOPE_UPDATE)?;
+    detach(async move { job(&state, &token, payload).await.map(Json) }).await
+}
+
+/// Run the job of the request, recording it in the history
+async fn job(
+    state: &AppState,
+    token: &Token,
+    payload: RunJobRequest,
+) -> Result<RunJobResponse, APIError> {
+    let transaction = transaction();
+    let service = state.docker.service_inspect(&payload.service).await?;
+    // the job runs with the secrets, env and mounts of the service
+    if service.label(&state.settings().config.protected_label) == Some("true")
+        && token.require(SCOPE_APPROVER).is_err()
+    {
+        return Err(APIError::forbidden(&format!(
+            "Service {} is protected, its jobs need the {} scope",
+            service.spec.name, SCOPE_APPROVER
+        )));
+    }
+    let image = payload
+        .image
+        .unwrap_or_else(|| service.spec.task_template.container_spec.image.clone());
+    let name = format!(
+        "{}-job-{}",
+        service.spec.name,
+        &transaction.simple().to_string()[..8]
+    );
+    let spec = jobs::job_spec(&service, &name, &image, payload.command.as_deref());
+    info!(
+        "Running job {} of {} with {}",
+        name, service.spec.name, image
+    );
+    let timeout = Duration::from_secs(
+        payload
+            .timeout
+            .unwrap_or(state.settings().config.job_timeout),
+    );
+    let entry = HistoryEntry::new(
+        HistoryAction::Job,
+        &service.id,
+        &service.spec.name,
+        &token.name,
+    )
+    .with_images(None, Some(&image))
+    .with_transaction(&transaction);
+    let result = match jobs::run(&state.docker, &spec, timeout).await {
+        Ok(result) => result,
+        Err(e) => {
+            state.history.record(entry.with_error(&e));
+            return Err(e.into());
+        }
+    };
+    if !result.succeeded() {
+        let message = format!(
+            "Job {} {} with exit code {:?}",
+            name, result.state, result.exit_code
+        );
+        state.history.record(entry.with_error(&message));
+        let mut error = APIError::new(StatusCode::INTERNAL_SERVER_ERROR, "job_failed", &message);
+        error.args = vec![service.spec.name];
+        error.data = serde_json::to_value(&result).unwrap_or_default();
+        return Err(error);
+    }
+    state.history.record(entry);
+    Ok(RunJobResponse {
+        code: "200".to_string(),
+        transaction: transaction.to_string(),
+        message: "Job completed".to_string(),
+        args: vec![service.spec.name],
+        data: result,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct BackgroundJobResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: BackgroundJob,
+}
+
+/// Start `run` as a background job, answering `202` with the job
+///
+/// `run` receives the id of the job to record its progress. Its response (or
+/// its error) is the result of the job. It runs in a task of its own, so the
+/// job fails (instead of staying running) when it panics.
+pub(crate) fn background<F, Fut, T>(
+    state: &Arc<AppState>,
+    kind: &str,
+    actor: &str,
+    run: F,
+) -> Response
+where
+    F: FnOnce(Arc<AppState>, Uuid) -> Fut,
+    Fut: Future<Output = Result<T, APIError>> + Send + 'static,
+    T: Serialize + Send + 'static,
+{
+    let job = state.background.start(kind, actor);
+    info!("Background job {} ({}) started by {}", job.id, kind, actor);
+    let future = run(state.clone(), job.id);
+    let jobs_state = state.clone();
+    let id = job.id;
+    tokio::spawn(propagate(async move {
+        let progress = jobs_state.background.progress(id);
+        match detach(future).await {
+            Ok(response) => {
+                let result = serde_json::to_value(&response).unwrap_or_default();
+                let message = result["message"].as_str().unwrap_or("Done").to_owned();
+                progress.finish(true, &message, result);
+            }
+            Err(error) => {
+                progress.finish(false, &error.message, error.to_value());
+            }
+        }
+    }));
+    (
+        StatusCode::ACCEPTED,
+        Json(BackgroundJobResponse {
+            code: "202".to_string(),
+            transaction: transaction().to_string(),
+            message: format!("Job {} started", kind),
+            args: vec![],
+            data: job,
+        }),
+    )
+        .into_response()
+}
+
+/// The job, when the token can read it: its own jobs, or any with the `read` scope
+fn readable_job(state: &AppState, token: &Token, id: &str) -> Result<BackgroundJob, APIError> {
+    let not_found = || APIError::not_found(&format!("Job {} not found", id));
+    let id = Uuid::parse_str(id).map_err(|_| not_found())?;
+    let job = state.background.get(&id).ok_or_else(not_found)?;
+    if job.actor != token.name {
+        token.require(SCOPE_READ)?;
+    }
+    Ok(job)
+}
+
+/// Get a background job with its events and result
+pub async fn get_job(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(id): Path<String>,
+) -> Result<Json<BackgroundJobResponse>, APIError> {
+    let job = readable_job(&state, &token, &id)?;
+    Ok(Json(BackgroundJobResponse {
+        code: "200".to_string(),
+        transaction: transaction().to_string(),
+        message: "Job".to_string(),
+        args: vec![],
+        data: job,
+    }))
+}
+
+/// Stream the events of a background job until it finishes
+///
+/// The events already recorded come first, each event is a line of json
+/// (`application/x-ndjson`). The last event has the state `succeeded` or
+/// `failed`.
+pub async fn job_events(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(id): Path<String>,
+) -> Result<Response<Body>, APIError> {
+    let id = readable_job(&state, &token, &id)?.id;
+    let (job, receiver) = state
+        .background
+        .follow(&id)
+        .ok_or_else(|| APIError::not_found(&format!("Job {} not found", id)))?;
+    let finished = job.events.last().is_some_and(JobEvent::is_final);
+    let next = stream::unfold((receiver, finished), move |(mut receiver, finished)| {
+        let state = state.clone();
+        async move {
+            if finished {
+                return None;
+            }
+            loop {
+                match receiver.recv().await {
+                    Ok((event_id, event)) if event_id == id => {
+                        let finished = event.is_final();
+                        return Some((event, (receiver, finished)));
+                    }
+                    Ok(_) => continue,
+                    // some events are lost, the final one is read from the job
+                    Err(RecvError::Lagged(_)) => {
+                        let last = state
+                            .background
+                            .get(&id)
+                            .and_then(|job| job.events.last().cloned())
+                            .filter(JobEvent::is_final);
+                        if let Some(event) = last {
+                            return Some((event, (receiver, true)));
+                        }
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        }
+    });
+    let lines = stream::iter(job.events)
+        .chain(next)
+        .map(|event| serde_json::to_string(&event).map(|line| format!("{}\n", line)));
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(lines))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, services::background::JobState};
+
+    #[tokio::test]
+    async fn test_background_panic() {
+        let state = Arc::new(AppState::new(Config::default()));
+        let response = background(&state, "update", "ci", |_, _| async {
+            panic!("boom") as Result<(), APIError>
+        });
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let id = Uuid::parse_str(body["data"]["id"].as_str().unwrap()).unwrap();
+        for _ in 0..50 {
+            if state.background.get(&id).unwrap().state != JobState::Running {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(state.background.get(&id).unwrap().state, JobState::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_job_of_protected_service() {
+        use axum::routing::get;
+        let app = axum::Router::new().route(
+            "/services/:id",
+            get(|| async {
+                Json(serde_json::json!({
+                    "ID": "shop_api",
+                    "Version": { "Index": 1 },
+                    "CreatedAt": "2024-06-01T10:00:00Z",
+                    "UpdatedAt": "2024-06-01T10:00:00Z",
+                    "Spec": {
+                        "Name": "shop_api",
+                        "Labels": { "updater.protected": "true" },
+                        "TaskTemplate": { "ContainerSpec": { "Image": "shop/api:1.0" } }
+                    }
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let state = AppState::new(Config {
+            docker_url: url,
+            ..Config::default()
+        });
+        let token = Token {
+            name: "ci".into(),
+            scopes: vec![SCOPE_UPDATE.into()],
+        };
+        let payload = serde_json::from_value(serde_json::json!({ "service": "shop_api" })).unwrap();
+        let error = job(&state, &token, payload).await.unwrap_err();
+        assert_eq!(error.status, StatusCode::FORBIDDEN);
+        assert!(state.history.list(None).is_empty());
+    }
+}
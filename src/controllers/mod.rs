@@ -1,3 +1,12 @@
+pub mod approvals;
+pub mod auth;
 pub mod echo;
+pub mod health;
+pub mod history;
+pub mod jobs;
+pub mod releases;
+pub mod request;
+pub mod rollback;
+pub mod services;
 pub mod types;
 pub mod update;
@@ -0,0 +1,388 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+
+use super::{
+    auth::{Token, SCOPE_UPDATE},
+    request::{detach, transaction},
+    rollback::rollback_services,
+    types::APIError,
+    update::before_update,
+};
+use crate::{
+    services::{
+        docker::{
+            split_image,
+            types::{Service, ServiceResume},
+            STACK_NAMESPACE_LABEL,
+        },
+        history::{HistoryAction, HistoryEntry},
+        notify::{Notification, NotificationKind, ServiceChange},
+    },
+    AppState,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ReleaseImage {
+    image: String,
+    tag: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ReleaseRequest {
+    /// Stack namespace (`com.docker.stack.namespace`) of the release
+    stack: String,
+    images: Vec<ReleaseImage>,
+    /// Names of the services to update first, in this order. The other
+    /// services follow in the order of the images.
+    #[serde(default)]
+    order: Vec<String>,
+    /// Pull the images on the nodes before updating, defaults to the config
+    prepull: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ReleaseResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: Vec<ServiceResume>,
+}
+
+/// A service of the release with the image it is updated to
+struct ReleaseStep {
+    service: Service,
+    image: String,
+    tag: String,
+    digest: String,
+}
+
+/// Update all images of a stack at once
+///
+/// The digests of every image are resolved, and the pre-pulls and `pre` hooks
+/// of every service run, before any update. When an update fails, the services
+/// already updated are rolled back.
+pub async fn create_release(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Json(payload): Json<ReleaseRequest>,
+) -> Result<Json<ReleaseResponse>, APIError> {
+    token.require(SCOPE_UPDATE)?;
+    // runs to its end (with its rollback) even when the request times out
+    detach(async move { release(&state, &token, payload).await.map(Json) }).await
+}
+
+/// Apply the release, rolling back the services updated when an update fails
+async fn release(
+    state: &AppState,
+    token: &Token,
+    payload: ReleaseRequest,
+) -> Result<ReleaseResponse, APIError> {
+    let transaction = transaction();
+    let started_at = Utc::now();
+
+    let mut digests = vec![];
+    for item in &payload.images {
+        digests.push(
+            state
+                .settings()
+                .registry
+                .resolve_digest(&item.image, &item.tag)
+                .await?,
+        );
+    }
+    let services = state.docker.services_list().await?;
+    let steps = release_plan(
+        services,
+        &payload,
+        &digests,
+        &state.settings().config.protected_label,
+    )?;
+
+    let prepull = payload.prepull.unwrap_or(state.settings().config.prepull);
+    // the pre-pulls and `pre` hooks of every step run before the first update
+    for step in &steps {
+        let result = before_update(
+            state,
+            &step.service,
+            &step.image,
+            &step.tag,
+            Some(&step.digest),
+            prepull,
+        )
+        .await;
+        if let Err(e) = result {
+            warn!(
+                "Release {} failed preparing {}: {}",
+                payload.stack, step.service.spec.name, e
+            );
+            let entry = HistoryEntry::new(
+                HistoryAction::Update,
+                &step.service.id,
+                &step.service.spec.name,
+                &token.name,
+            )
+            .with_images(
+                Some(&step.service.spec.task_template.container_spec.image),
+                Some(&format!("{}:{}@{}", step.image, step.tag, step.digest)),
+            )
+            .with_transaction(&transaction);
+            state.history.record(entry.with_error(&e));
+            let change = ServiceChange::new(&step.service, &step.image, &step.tag);
+            state.settings().notifiers.dispatch(
+                Notification::new(
+                    NotificationKind::Release,
+                    &transaction,
+                    &token.name,
+                    started_at,
+                )
+                .with_request(&payload)
+                .with_changes(vec![change.with_error(&e)])
+                .with_error(&e),
+            );
+            let mut error = APIError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "release_failed",
+                &format!("Error preparing {}: {}", step.service.spec.name, e),
+            );
+            error.args = vec![payload.stack.clone()];
+            error.data = json!({
+                "failed": step.service.spec.name,
+                "rolledBack": [],
+                "rollbackErrors": [],
+            });
+            return Err(error);
+        }
+    }
+
+    let mut updated: Vec<Service> = vec![];
+    let mut changes = vec![];
+    for mut step in steps {
+        info!(
+            "Release {}: updating {} to {}:{}@{}",
+            payload.stack, step.service.spec.name, step.image, step.tag, step.digest
+        );
+        let from_image = step.service.spec.task_template.container_spec.image.clone();
+        let change = ServiceChange::new(&step.service, &step.image, &step.tag);
+        let _expected = state
+            .history
+            .expect(&step.service.id, &format!("{}:{}", step.image, step.tag));
+        let result = step
+            .service
+            .update_image_digest(&step.image, &step.tag, &step.digest)
+            .await;
+        let entry = HistoryEntry::new(
+            HistoryAction::Update,
+            &step.service.id,
+            &step.service.spec.name,
+            &token.name,
+        )
+        .with_images(
+            Some(&from_image),
+            Some(&step.service.spec.task_template.container_spec.image),
+        )
+        .with_transaction(&transaction);
+        if let Err(e) = result {
+            state.history.record(entry.with_error(&e));
+            warn!(
+                "Release {} failed on {}: {}",
+                payload.stack, step.service.spec.name, e
+            );
+            changes.push(change.with_error(&e));
+            state.settings().notifiers.dispatch(
+                Notification::new(
+                    NotificationKind::Release,
+                    &transaction,
+                    &token.name,
+                    started_at,
+                )
+                .with_request(&payload)
+                .with_changes(changes)
+                .with_error(&e),
+            );
+            let (rolled_back, errors) =
+                rollback_services(state, &updated, &token.name, &transaction, &payload).await;
+            let mut error = APIError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "release_failed",
+                &format!("Error updating {}: {}", step.service.spec.name, e),
+            );
+            error.args = vec![payload.stack.clone()];
+            error.data = json!({
+                "failed": step.service.spec.name,
+                "rolledBack": rolled_back,
+                "rollbackErrors": errors,
+            });
+            return Err(error);
+        }
+        state.history.record(entry);
+        state
+            .settings()
+            .hooks
+            .after(&state.docker, &step.service, &step.image, &step.tag)
+            .await;
+        changes.push(change);
+        updated.push(step.service);
+    }
+    state.settings().notifiers.dispatch(
+        Notification::new(
+            NotificationKind::Release,
+            &transaction,
+            &token.name,
+            started_at,
+        )
+        .with_request(&payload)
+        .with_changes(changes),
+    );
+
+    Ok(ReleaseResponse {
+        code: "200".to_string(),
+        transaction: transaction.to_string(),
+        message: "Release applied".to_string(),
+        args: vec![payload.stack],
+        data: updated.into_iter().map(ServiceResume::from).collect(),
+    })
+}
+
+/// Match the services of the stack with the images of the release, in the
+/// order they must be updated
+fn release_plan(
+    services: Vec<Service>,
+    payload: &ReleaseRequest,
+    digests: &[String],
+    protected_label: &str,
+) -> Result<Vec<ReleaseStep>, APIError> {
+    let services = services
+        .into_iter()
+        .filter(|service| service.label(STACK_NAMESPACE_LABEL) == Some(payload.stack.as_str()))
+        .collect::<Vec<Service>>();
+    let mut steps = vec![];
+    for (item, digest) in payload.images.iter().zip(digests) {
+        let matched = services
+            .iter()
+            .filter(|service| {
+                split_image(&service.spec.task_template.container_spec.image).0 == item.image
+            })
+            .collect::<Vec<&Service>>();
+        if matched.is_empty() {
+            return Err(APIError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_release",
+                &format!(
+                    "No service of stack {} uses image {}",
+                    payload.stack, item.image
+                ),
+            ));
+        }
+        for service in matched {
+            if service.label(protected_label) == Some("true") {
+                return Err(APIError::forbidden(&format!(
+                    "Service {} is protected, it must be updated with an approval",
+                    service.spec.name
+                )));
+            }
+            steps.push(ReleaseStep {
+                service: service.clone(),
+                image: item.image.clone(),
+                tag: item.tag.clone(),
+                digest: digest.clone(),
+            });
+        }
+    }
+    let position = |step: &ReleaseStep| {
+        payload
+            .order
+            .iter()
+            .position(|name| *name == step.service.spec.name)
+            .unwrap_or(payload.order.len())
+    };
+    steps.sort_by_key(position);
+    Ok(steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, stack: &str, image: &str) -> Service {
+        serde_json::from_value(json!({
+            "ID": name,
+            "Version": { "Index": 1 },
+            "CreatedAt": "2024-06-01T10:00:00Z",
+            "UpdatedAt": "2024-06-01T10:00:00Z",
+            "Spec": {
+                "Name": name,
+                "Labels": { STACK_NAMESPACE_LABEL: stack },
+                "TaskTemplate": { "ContainerSpec": { "Image": image } }
+            }
+        }))
+        .unwrap()
+    }
+
+    fn request(order: &[&str]) -> ReleaseRequest {
+        serde_json::from_value(json!({
+            "stack": "shop",
+            "images": [
+                { "image": "shop/api", "tag": "2.0" },
+                { "image": "shop/migrator", "tag": "2.0" },
+            ],
+            "order": order,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_release_plan_order() {
+        let services = vec![
+            service("shop_api", "shop", "shop/api:1.0"),
+            service("shop_worker", "shop", "shop/api:1.0@sha256:0"),
+            service("shop_migrator", "shop", "shop/migrator:1.0"),
+            service("other_api", "other", "shop/api:1.0"),
+        ];
+        let digests = vec!["sha256:1".to_owned(), "sha256:2".to_owned()];
+        let steps = release_plan(services, &request(&["shop_migrator"]), &digests, "p").unwrap();
+        let names = steps
+            .iter()
+            .map(|step| step.service.spec.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, vec!["shop_migrator", "shop_api", "shop_worker"]);
+        assert_eq!(steps[0].digest, "sha256:2");
+    }
+
+    #[test]
+    fn test_release_plan_image_references() {
+        let services = vec![
+            service("shop_api", "shop", "shop/api@sha256:0"),
+            service("shop_migrator", "shop", "shop/migrator:1.0"),
+            service("shop_registry", "shop", "shop/api:5000/app:1"),
+        ];
+        let digests = vec!["sha256:1".to_owned(), "sha256:2".to_owned()];
+        let names = release_plan(services, &request(&[]), &digests, "p")
+            .unwrap()
+            .into_iter()
+            .map(|step| step.service.spec.name)
+            .collect::<Vec<String>>();
+        assert_eq!(names, vec!["shop_api", "shop_migrator"]);
+
+        // a registry with a port is not the tag of an image
+        let services = vec![service("shop_app", "shop", "localhost:5000/app:1")];
+        let request: ReleaseRequest = serde_json::from_value(json!({
+            "stack": "shop",
+            "images": [{ "image": "localhost", "tag": "2.0" }],
+        }))
+        .unwrap();
+        assert!(release_plan(services, &request, &digests[..1], "p").is_err());
+    }
+
+    #[test]
+    fn test_release_plan_missing_image() {
+        let services = vec![service("shop_api", "shop", "shop/api:1.0")];
+        let digests = vec!["sha256:1".to_owned(), "sha256:2".to_owned()];
+        assert!(release_plan(services, &request(&[]), &digests, "p").is_err());
+    }
+}
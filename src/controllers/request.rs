@@ -0,0 +1,165 @@
+//! # Request correlation
+//!
+//! Each request runs in a `request` span with its transaction id, the client
+//! ip and, once authenticated, the name of the token: every log of the request
+//! carries them. The transaction id is the `X-Request-Id` of the request when
+//! it is an uuid (set by a proxy), or a new one. It is the `transaction` of the
+//! response and is returned in its `X-Request-Id` header.
+//!
+//! The span is exported with the `traceparent` of the request as parent, see
+//! [telemetry](crate::telemetry).
+//!
+use std::{future::Future, net::SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{HeaderName, HeaderValue, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{debug, field, info_span, Instrument, Span};
+use uuid::Uuid;
+
+use super::types::APIError;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static TRANSACTION: Uuid;
+}
+
+/// The transaction id of the current request, or a new one outside requests
+pub fn transaction() -> Uuid {
+    TRANSACTION
+        .try_with(|transaction| *transaction)
+        .unwrap_or_else(|_| Uuid::new_v4())
+}
+
+/// Keep the transaction and the span of the request in a spawned future
+pub fn propagate<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    TRANSACTION.scope(transaction(), future.instrument(Span::current()))
+}
+
+/// Run the future in its own task until it finishes, even when the request is
+/// dropped (by the request timeout or a closed connection): an update is not
+/// stopped halfway, with its temporary services left behind
+pub async fn detach<F, T>(future: F) -> Result<T, APIError>
+where
+    F: Future<Output = Result<T, APIError>> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::spawn(propagate(future)).await.unwrap_or_else(|e| {
+        Err(APIError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "error",
+            &format!("Task failed: {}", e),
+        ))
+    })
+}
+
+/// Record the name of the token in the span of the request
+pub fn record_token(name: &str) {
+    Span::current().record("token", name);
+}
+
+/// Middleware running the request in its span
+pub async fn request_span(request: Request, next: Next) -> Response {
+    let header = request.headers().get(&REQUEST_ID_HEADER);
+    let transaction = match header.and_then(|value| value.to_str().ok()) {
+        Some(value) => Uuid::parse_str(value).unwrap_or_else(|_| {
+            debug!("Ignoring the X-Request-Id {}, not an uuid", value);
+            Uuid::new_v4()
+        }),
+        None => Uuid::new_v4(),
+    };
+    let ip = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip().to_string());
+    let forwarded_for = request
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok());
+    let span = info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        transaction = %transaction,
+        ip = ip.as_deref().unwrap_or("unknown"),
+        forwarded_for,
+        token = field::Empty,
+    );
+    #[cfg(feature = "otel")]
+    crate::telemetry::set_parent(&span, request.headers());
+    let mut response = TRANSACTION
+        .scope(transaction, next.run(request).instrument(span))
+        .await;
+    response.headers_mut().insert(
+        REQUEST_ID_HEADER,
+        HeaderValue::from_str(&transaction.to_string()).expect("an uuid is a valid header"),
+    );
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{middleware, routing::get, Router};
+
+    #[tokio::test]
+    async fn test_request_span() {
+        let app = Router::new()
+            .route("/", get(|| async { transaction().to_string() }))
+            .layer(middleware::from_fn(request_span));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let id = Uuid::new_v4();
+        let response = client
+            .get(&url)
+            .header(REQUEST_ID_HEADER, id.to_string())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], id.to_string());
+        assert_eq!(response.text().await.unwrap(), id.to_string());
+
+        let response = client
+            .get(&url)
+            .header(REQUEST_ID_HEADER, "not-an-uuid")
+            .send()
+            .await
+            .unwrap();
+        let header = response.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert!(Uuid::parse_str(&header).is_ok());
+        assert_eq!(response.text().await.unwrap(), header);
+    }
+
+    #[tokio::test]
+    async fn test_detach_outlives_the_request() {
+        let (sender, receiver) = tokio::sync::oneshot::channel();
+        let request = detach(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            let _ = sender.send(());
+            Ok(())
+        });
+        // the request times out before the end of the task
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(10), request)
+                .await
+                .is_err()
+        );
+        assert!(receiver.await.is_ok());
+
+        let panicked = detach(async { panic!("boom") as Result<(), APIError> }).await;
+        assert_eq!(
+            panicked.unwrap_err().status,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{
+    auth::{Token, SCOPE_UPDATE},
+    jobs::background,
+    request::transaction,
+    types::APIError,
+};
+use crate::{
+    services::{
+        background::Progress,
+        docker::types::{Service, ServiceResume},
+        history::{HistoryAction, HistoryEntry},
+        notify::{Notification, NotificationKind, ServiceChange},
+    },
+    AppState,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct RollbackRequest {
+    /// Name or id of the service
+    service: String,
+    /// Answer at once, running the rollback as a background job
+    #[serde(default)]
+    background: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct RollbackResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: Vec<ServiceResume>,
+}
+
+/// Rollback the service to its previous spec
+///
+/// A protected service is not rolled back: its changes need an approval.
+///
+/// With `background`, the rollback runs after the response, which has its job.
+pub async fn rollback_service(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Json(payload): Json<RollbackRequest>,
+) -> Result<Response, APIError> {
+    token.require(SCOPE_UPDATE)?;
+    if payload.background {
+        let actor = token.name.clone();
+        return Ok(background(
+            &state,
+            "rollback",
+            &actor,
+            |state, id| async move {
+                rollback(&state, &token, &payload, &state.background.progress(id)).await
+            },
+        ));
+    }
+    Ok(Json(rollback(&state, &token, &payload, &Progress::none()).await?).into_response())
+}
+
+/// Rollback the service of the request, recording the progress
+pub(crate) async fn rollback(
+    state: &AppState,
+    token: &Token,
+    payload: &RollbackRequest,
+    progress: &Progress<'_>,
+) -> Result<RollbackResponse, APIError> {
+    let transaction = transaction();
+    let service = state.docker.service_inspect(&payload.service).await?;
+    if service.label(&state.settings().config.protected_label) == Some("true") {
+        let mut error = APIError::new(
+            StatusCode::CONFLICT,
+            "protected_service",
+            &format!(
+                "Service {} is protected, it must be changed with an approval",
+                service.spec.name
+            ),
+        );
+        error.args = vec![service.spec.name];
+        return Err(error);
+    }
+    if service.previous_spec.is_none() {
+        return Err(APIError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_rollback",
+            &format!("Service {} has no previous spec", service.spec.name),
+        ));
+    }
+    info!(
+        "Rolling back {} requested by {}",
+        service.spec.name, token.name
+    );
+    progress.event(Some(&service.spec.name), "Rolling back");
+    let (_, errors) = rollback_services(
+        state,
+        std::slice::from_ref(&service),
+        &token.name,
+        &transaction,
+        payload,
+    )
+    .await;
+    if !errors.is_empty() {
+        let mut error = APIError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "rollback_failed",
+            &errors.join(", "),
+        );
+        error.args = vec![service.spec.name];
+        return Err(error);
+    }
+    let service = state.docker.service_inspect(&service.id).await?;
+    Ok(RollbackResponse {
+        code: "200".to_string(),
+        transaction: transaction.to_string(),
+        message: "Service rolled back".to_string(),
+        args: vec![],
+        data: vec![ServiceResume::from(service)],
+    })
+}
+
+/// Rollback the services, the last updated first
+///
+/// Returns the names of the services rolled back and the errors.
+pub(crate) async fn rollback_services(
+    state: &AppState,
+    services: &[Service],
+    actor: &str,
+    transaction: &Uuid,
+    request: &impl Serialize,
+) -> (Vec<String>, Vec<String>) {
+    let started_at = Utc::now();
+    let mut rolled_back = vec![];
+    let mut errors = vec![];
+    let mut changes = vec![];
+    for service in services.iter().rev() {
+        let entry = HistoryEntry::new(
+            HistoryAction::Rollback,
+            &service.id,
+            &service.spec.name,
+            actor,
+        )
+        .with_transaction(transaction);
+        // kept until the rollback is recorded
+        let mut expected = None;
+        let result = match state.docker.service_inspect(&service.id).await {
+            Ok(current) => {
+                let previous = current
+                    .previous_spec
+                    .as_ref()
+                    .map(|spec| spec.task_template.container_spec.image.clone());
+                let entry = entry.clone().with_images(
+                    Some(&current.spec.task_template.container_spec.image),
+                    previous.as_deref(),
+                );
+                // the service as it was before the release
+                let mut target = current.clone();
+                if let Some(spec) = &current.previous_spec {
+                    target.spec = spec.clone();
+                }
+                let target = ServiceResume::from(target);
+                let change = ServiceChange::new(&current, &target.image, &target.tag);
+                expected = previous
+                    .as_deref()
+                    .map(|image| state.history.expect(&service.id, image));
+                current.rollback().await.map(|_| (entry, change))
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((entry, change)) => {
+                state.history.record(entry);
+                changes.push(change);
+                rolled_back.push(service.spec.name.clone());
+            }
+            Err(e) => {
+                warn!("Error rolling back {}: {}", service.spec.name, e);
+                state.history.record(entry.with_error(&e));
+                let resume = ServiceResume::from(service.clone());
+                changes
+                    .push(ServiceChange::new(service, &resume.image, &resume.tag).with_error(&e));
+                errors.push(format!("{}: {}", service.spec.name, e));
+            }
+        }
+        drop(expected);
+    }
+    if !changes.is_empty() {
+        let notification =
+            Notification::new(NotificationKind::Rollback, transaction, actor, started_at)
+                .with_request(request)
+                .with_changes(changes);
+        state.settings().notifiers.dispatch(if errors.is_empty() {
+            notification
+        } else {
+            notification.with_error(&errors.join(", "))
+        });
+    }
+    (rolled_back, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::routing::get;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_rollback_protected_service() {
+        let app = axum::Router::new().route(
+            "/services/:id",
+            get(|| async {
+                Json(json!({
+                    "ID": "shop_api",
+                    "Version": { "Index": 2 },
+                    "CreatedAt": "2024-06-01T10:00:00Z",
+                    "UpdatedAt": "2024-06-02T10:00:00Z",
+                    "Spec": {
+                        "Name": "shop_api",
+                        "Labels": { "updater.protected": "true" },
+                        "TaskTemplate": { "ContainerSpec": { "Image": "shop/api:2.0" } }
+                    },
+                    "PreviousSpec": {
+                        "Name": "shop_api",
+                        "Labels": { "updater.protected": "true" },
+                        "TaskTemplate": { "ContainerSpec": { "Image": "shop/api:1.0" } }
+                    }
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let state = AppState::new(Config {
+            docker_url: url,
+            ..Config::default()
+        });
+        let payload = serde_json::from_value(json!({ "service": "shop_api" })).unwrap();
+        // the command line has every scope, and still needs an approval
+        let error = rollback(&state, &Token::local("cli"), &payload, &Progress::none())
+            .await
+            .unwrap_err();
+        assert_eq!(error.status, StatusCode::CONFLICT);
+        assert_eq!(error.code, "protected_service");
+        assert!(state.history.list(None).is_empty());
+    }
+}
@@ -0,0 +1,222 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, Response},
+    Json,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    auth::{Token, SCOPE_LOGS, SCOPE_READ},
+    request::transaction,
+    types::APIError,
+};
+use crate::{
+    services::docker::{
+        logs::LogsOptions,
+        split_image,
+        types::{Service, ServiceDetails, TaskResume},
+        STACK_NAMESPACE_LABEL,
+    },
+    AppState,
+};
+
+/// Filters of the service list
+///
+/// * image: Image name, without tag
+/// * stack: Stack namespace
+/// * label: Label key, or `key=value`
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct ServicesQuery {
+    image: Option<String>,
+    stack: Option<String>,
+    label: Option<String>,
+}
+
+impl ServicesQuery {
+    fn matches(&self, service: &Service) -> bool {
+        if let Some(image) = &self.image {
+            let current = &service.spec.task_template.container_spec.image;
+            if current != image && split_image(current).0 != image {
+                return false;
+            }
+        }
+        if let Some(stack) = &self.stack {
+            if service.label(STACK_NAMESPACE_LABEL) != Some(stack.as_str()) {
+                return false;
+            }
+        }
+        if let Some(label) = &self.label {
+            let matched = match label.split_once('=') {
+                Some((key, value)) => service.label(key) == Some(value),
+                None => service.label(label).is_some(),
+            };
+            if !matched {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ServicesResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: Vec<ServiceDetails>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct ServiceResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: ServiceDetails,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TasksResponse {
+    code: String,
+    transaction: String,
+    message: String,
+    args: Vec<String>,
+    data: Vec<TaskResume>,
+}
+
+/// List the services deployed in the swarm
+pub async fn list_services(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Query(query): Query<ServicesQuery>,
+) -> Result<Json<ServicesResponse>, APIError> {
+    token.require(SCOPE_READ)?;
+    let services = state.docker.services_list().await?;
+    let mut services = services
+        .into_iter()
+        .filter(|service| query.matches(service))
+        .map(ServiceDetails::from)
+        .collect::<Vec<ServiceDetails>>();
+    services.sort_by(|a, b| a.resume.name.cmp(&b.resume.name));
+    Ok(Json(ServicesResponse {
+        code: "200".to_string(),
+        transaction: transaction().to_string(),
+        message: "Services".to_string(),
+        args: vec![],
+        data: services,
+    }))
+}
+
+/// Get a service by name (or id)
+pub async fn get_service(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(name): Path<String>,
+) -> Result<Json<ServiceResponse>, APIError> {
+    token.require(SCOPE_READ)?;
+    let service = state.docker.service_inspect(&name).await?;
+    // the task counters are only returned by the list
+    let status = state.docker.service_status(&service.id).await.ok();
+    let mut service = ServiceDetails::from(service);
+    service.running_tasks = status.as_ref().map(|status| status.running_tasks);
+    service.desired_tasks = status.as_ref().map(|status| status.desired_tasks);
+    Ok(Json(ServiceResponse {
+        code: "200".to_string(),
+        transaction: transaction().to_string(),
+        message: "Service".to_string(),
+        args: vec![name],
+        data: service,
+    }))
+}
+
+/// List the tasks of a service, with the node and image digest of each one
+pub async fn list_tasks(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(name): Path<String>,
+) -> Result<Json<TasksResponse>, APIError> {
+    token.require(SCOPE_READ)?;
+    let service = state.docker.service_inspect(&name).await?;
+    let tasks = state.docker.tasks_list(Some(&service.id)).await?;
+    let nodes = state.docker.nodes_list().await?;
+    let mut tasks = tasks
+        .into_iter()
+        .map(|task| TaskResume::new(task, &nodes))
+        .collect::<Vec<TaskResume>>();
+    tasks.sort_by(|a, b| {
+        a.slot
+            .cmp(&b.slot)
+            .then_with(|| b.updated_at.cmp(&a.updated_at))
+    });
+    Ok(Json(TasksResponse {
+        code: "200".to_string(),
+        transaction: transaction().to_string(),
+        message: "Tasks".to_string(),
+        args: vec![name],
+        data: tasks,
+    }))
+}
+
+/// Stream the logs of a service
+///
+/// Each frame of stdout/stderr is sent as a line of json (`application/x-ndjson`):
+/// `{"stream": "stdout", "message": "..."}`.
+pub async fn service_logs(
+    State(state): State<Arc<AppState>>,
+    token: Token,
+    Path(name): Path<String>,
+    Query(options): Query<LogsOptions>,
+) -> Result<Response<Body>, APIError> {
+    token.require(SCOPE_LOGS)?;
+    let service = state.docker.service_inspect(&name).await?;
+    let frames = state.docker.service_logs(&service.id, &options).await?;
+    let lines = frames.map(|frame| {
+        let line = match frame {
+            Ok(frame) => serde_json::to_string(&frame),
+            Err(e) => serde_json::to_string(&serde_json::json!({ "error": e.to_string() })),
+        };
+        line.map(|line| format!("{}\n", line))
+    });
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-ndjson")
+        .body(Body::from_stream(lines))
+        .unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_services_query() {
+        let service: Service = serde_json::from_value(json!({
+            "ID": "abc",
+            "Version": { "Index": 1 },
+            "CreatedAt": "2024-06-01T10:00:00Z",
+            "UpdatedAt": "2024-06-01T10:00:00Z",
+            "Spec": {
+                "Name": "shop_api",
+                "Labels": { STACK_NAMESPACE_LABEL: "shop", "tier": "back" },
+                "TaskTemplate": { "ContainerSpec": { "Image": "shop/api:1.0@sha256:0" } }
+            }
+        }))
+        .unwrap();
+        let query = |image: Option<&str>, stack: Option<&str>, label: Option<&str>| ServicesQuery {
+            image: image.map(String::from),
+            stack: stack.map(String::from),
+            label: label.map(String::from),
+        };
+        assert!(query(None, None, None).matches(&service));
+        assert!(query(Some("shop/api"), Some("shop"), Some("tier=back")).matches(&service));
+        assert!(query(None, None, Some("tier")).matches(&service));
+        assert!(!query(Some("shop/ap"), None, None).matches(&service));
+        assert!(!query(None, Some("other"), None).matches(&service));
+        assert!(!query(None, None, Some("tier=front")).matches(&service));
+    }
+}
@@ -1,17 +1,60 @@
-use axum::{body::Body, http::Response, response::IntoResponse};
+use axum::{
+    body::Body,
+    http::{Response, StatusCode},
+    response::IntoResponse,
+};
 use serde_json::{json, Value};
-use uuid::Uuid;
 
+use super::request::transaction;
+use crate::services::{docker::error::DockerError, registry::error::RegistryError};
+
+#[derive(Debug)]
 pub struct APIError {
+    pub status: StatusCode,
     pub code: String,
     pub message: String,
     pub args: Vec<String>,
     pub data: Value,
 }
 
+impl APIError {
+    pub fn new(status: StatusCode, code: &str, message: &str) -> Self {
+        APIError {
+            status,
+            code: code.into(),
+            message: message.into(),
+            args: vec![],
+            data: json!({}),
+        }
+    }
+
+    pub fn unauthorized(message: &str) -> Self {
+        APIError::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        APIError::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        APIError::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    /// The error as json, without the transaction of the response
+    pub fn to_value(&self) -> Value {
+        json!({
+            "code": self.code,
+            "message": self.message,
+            "args": self.args,
+            "data": self.data,
+        })
+    }
+}
+
 impl From<anyhow::Error> for APIError {
     fn from(value: anyhow::Error) -> Self {
         APIError {
+            status: StatusCode::INTERNAL_SERVER_ERROR,
             code: "error".into(),
             message: format!("{:?}", value),
             args: vec![],
@@ -20,9 +63,53 @@ impl From<anyhow::Error> for APIError {
     }
 }
 
+impl From<DockerError> for APIError {
+    fn from(value: DockerError) -> Self {
+        if let DockerError::ServiceNotFound(_) = value {
+            return APIError::not_found(&value.to_string());
+        }
+        if let DockerError::InvalidChange(_) = value {
+            return APIError::new(
+                StatusCode::BAD_REQUEST,
+                "invalid_change",
+                &value.to_string(),
+            );
+        }
+        if let DockerError::HookError(_) = value {
+            return APIError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "hook_failed",
+                &value.to_string(),
+            );
+        }
+        APIError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "docker_error",
+            &value.to_string(),
+        )
+    }
+}
+
+impl From<RegistryError> for APIError {
+    fn from(value: RegistryError) -> Self {
+        if let RegistryError::ManifestNotFound(_) = value {
+            return APIError::new(
+                StatusCode::BAD_REQUEST,
+                "image_not_found",
+                &value.to_string(),
+            );
+        }
+        APIError::new(
+            StatusCode::BAD_GATEWAY,
+            "registry_error",
+            &value.to_string(),
+        )
+    }
+}
+
 impl IntoResponse for APIError {
     fn into_response(self) -> Response<Body> {
-        let transaction = Uuid::new_v4();
+        let transaction = transaction();
         let response = json!({
             "code": self.code,
             "transaction": transaction.to_string(),
@@ -31,7 +118,7 @@ impl IntoResponse for APIError {
             "data": self.data,
         });
         axum::http::Response::builder()
-            .status(500)
+            .status(self.status)
             .header("Content-Type", "application/json")
             .body(Body::from(serde_json::to_string(&response).unwrap()))
             .unwrap()
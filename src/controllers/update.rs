@@ -1,18 +1,59 @@
-use futures::stream::StreamExt;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use axum::{extract::State, Json};
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tracing::{info, warn};
 use uuid::Uuid;
 
-use crate::{services::docker::types::ServiceResume, AppState};
+use super::{
+    auth::{Token, SCOPE_UPDATE},
+    jobs::background,
+    request::{detach, propagate, transaction},
+    types::APIError,
+};
+use crate::{
+    services::{
+        approvals::PendingChange,
+        background::Progress,
+        canary::{self, CanaryOptions},
+        docker::{
+            error::DockerError,
+            split_image,
+            types::{RolloutConfig, Service, ServiceResume, ServiceUpdateOptions},
+        },
+        history::{HistoryAction, HistoryEntry},
+        notify::{Notification, NotificationKind, Outcome, ServiceChange},
+        prepull,
+    },
+    AppState,
+};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct UpdateServiceRequest {
     image: String,
     tag: String,
     service: Option<String>,
+    canary: Option<CanaryRequest>,
+    /// Answer at once, running the update as a background job
+    #[serde(default)]
+    background: bool,
+    /// Pull the image on the nodes before updating, defaults to the config
+    prepull: Option<bool>,
+    /// Env, labels and update config changes, sent in the same service update
+    #[serde(flatten)]
+    options: ServiceUpdateOptions,
+}
+
+/// Canary rollout of the update, defaults come from the config
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct CanaryRequest {
+    replicas: Option<u64>,
+    healthy_period: Option<u64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -22,46 +63,661 @@ pub(crate) struct UpdateServiceResponse {
     message: String,
     args: Vec<String>,
     data: Vec<ServiceResume>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pending: Vec<PendingChange>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    canary: Vec<ServiceResume>,
 }
 
+/// Update every service using the image (or only the named service)
+///
+/// Services labeled as protected are not updated: a pending change is
+/// created instead, waiting for an approver.
+///
+/// When a canary is requested, the canary and the update of each service run
+/// in background, as they usually take longer than the request timeout.
+///
+/// Without `background`, the update still runs to its end when the request
+/// times out, see [detach].
+///
+/// With `background`, the update runs after the response, which has its job
+/// (see [background](crate::services::background)).
 pub async fn update_service(
     State(state): State<Arc<AppState>>,
+    token: Token,
     Json(payload): Json<UpdateServiceRequest>,
-) -> Json<UpdateServiceResponse> {
-    let transaction = Uuid::new_v4();
+) -> Result<Response, APIError> {
+    token.require(SCOPE_UPDATE)?;
+    if payload.background {
+        let actor = token.name.clone();
+        return Ok(background(
+            &state,
+            "update",
+            &actor,
+            |state, id| async move {
+                update(&state, &token, &payload, &state.background.progress(id)).await
+            },
+        ));
+    }
+    let response =
+        detach(async move { update(&state, &token, &payload, &Progress::none()).await }).await?;
+    Ok(Json(response).into_response())
+}
+
+/// Update the services of the request, recording the progress
+pub(crate) async fn update(
+    state: &Arc<AppState>,
+    token: &Token,
+    payload: &UpdateServiceRequest,
+    progress: &Progress<'_>,
+) -> Result<UpdateServiceResponse, APIError> {
+    let transaction = transaction();
+    let started_at = Utc::now();
+    let notification = || {
+        Notification::new(
+            NotificationKind::Update,
+            &transaction,
+            &token.name,
+            started_at,
+        )
+        .with_request(payload)
+    };
+
+    let prepull = payload.prepull.unwrap_or(state.settings().config.prepull);
+    let services = select_services(state.docker.services_list().await?, payload)?;
+    let mut updated = vec![];
+    let mut pending = vec![];
+    let mut canaries = vec![];
+    let mut changes = vec![];
+    let mut pending_changes = vec![];
+    let mut protected = vec![];
+    let mut ready = vec![];
+    for mut service in services {
+        if service.label(&state.settings().config.protected_label) == Some("true") {
+            // validate the options now, they are applied on approval
+            service.clone().apply_options(&payload.options)?;
+            protected.push(service);
+            continue;
+        }
+        if let Some(request) = &payload.canary {
+            let options = CanaryOptions {
+                replicas: request
+                    .replicas
+                    .unwrap_or(state.settings().config.canary_replicas),
+                healthy_period: request
+                    .healthy_period
+                    .unwrap_or(state.settings().config.canary_healthy_period),
+                timeout: state.settings().config.canary_timeout,
+            };
+            service.apply_options(&payload.options)?;
+            canary::canary_spec(&service, &payload.image, &payload.tag, options.replicas)?;
+            canaries.push(ServiceResume::from(service.clone()));
+            progress.event(Some(&service.spec.name), "Canary started");
+            tokio::spawn(propagate(canary_update(
+                state.clone(),
+                CanaryUpdate {
+                    service,
+                    image: payload.image.clone(),
+                    tag: payload.tag.clone(),
+                    update_options: payload.options.clone(),
+                    options,
+                    prepull,
+                    actor: token.name.clone(),
+                    transaction,
+                    request: serde_json::to_value(payload).unwrap_or_default(),
+                },
+            )));
+            continue;
+        }
+        let mut logged = service.clone();
+        state.settings().redactor.service(&mut logged);
+        info!("Updating service: {:?}", logged);
+        let rollout = service.rollout_config();
+        service.apply_options(&payload.options)?;
+        ready.push((service, rollout));
+    }
+
+    // the pre-pulls and `pre` hooks of every service run before the first
+    // update: a failing hook does not leave the services half updated
+    let image = format!("{}:{}", payload.image, payload.tag);
+    for (service, _) in &ready {
+        progress.event(Some(&service.spec.name), "Preparing the update");
+        if let Err(e) =
+            before_update(state, service, &payload.image, &payload.tag, None, prepull).await
+        {
+            progress.event(Some(&service.spec.name), &format!("Update failed: {}", e));
+            let entry = HistoryEntry::new(
+                HistoryAction::Update,
+                &service.id,
+                &service.spec.name,
+                &token.name,
+            )
+            .with_images(
+                Some(&service.spec.task_template.container_spec.image),
+                Some(&image),
+            )
+            .with_transaction(&transaction);
+            state.history.record(entry.with_error(&e));
+            let change = ServiceChange::new(service, &payload.image, &payload.tag);
+            state.settings().notifiers.dispatch(
+                notification()
+                    .with_changes(vec![change.with_error(&e)])
+                    .with_error(&e),
+            );
+            return Err(e.into());
+        }
+    }
+
+    for (mut service, rollout) in ready {
+        let from_image = service.spec.task_template.container_spec.image.clone();
+        let change = ServiceChange::new(&service, &payload.image, &payload.tag);
+        progress.event(
+            Some(&service.spec.name),
+            &format!("Updating to {}:{}", payload.image, payload.tag),
+        );
+        let _expected = state.history.expect(&service.id, &image);
+        let result = service.update_image(&payload.image, &payload.tag).await;
+        let entry = HistoryEntry::new(
+            HistoryAction::Update,
+            &service.id,
+            &service.spec.name,
+            &token.name,
+        )
+        .with_images(
+            Some(&from_image),
+            Some(&service.spec.task_template.container_spec.image),
+        )
+        .with_transaction(&transaction);
+        if let Err(e) = result {
+            progress.event(Some(&service.spec.name), &format!("Update failed: {}", e));
+            state.history.record(entry.with_error(&e));
+            changes.push(change.with_error(&e));
+            state
+                .settings()
+                .notifiers
+                .dispatch(notification().with_changes(changes).with_error(&e));
+            return Err(e.into());
+        }
+        state.history.record(entry);
+        if payload.options.changes_rollout() {
+            tokio::spawn(propagate(restore_rollout_config(
+                state.clone(),
+                service.id.clone(),
+                rollout,
+            )));
+        }
+        state
+            .settings()
+            .hooks
+            .after(&state.docker, &service, &payload.image, &payload.tag)
+            .await;
+        progress.event(Some(&service.spec.name), "Updated");
+        changes.push(change);
+        updated.push(ServiceResume::from(service));
+    }
+
+    // the changes wait approval only once the rest of the update succeeded
+    for service in protected {
+        info!(
+            "Service {} is protected, waiting approval",
+            service.spec.name
+        );
+        progress.event(Some(&service.spec.name), "Waiting approval");
+        pending_changes.push(ServiceChange::new(&service, &payload.image, &payload.tag));
+        let mut change = state.approvals.add(
+            &service.id,
+            &service.spec.name,
+            &payload.image,
+            &payload.tag,
+            &payload.options,
+            &token.name,
+        );
+        // the response is also the result of the background job
+        state.settings().redactor.options(&mut change.options);
+        pending.push(change);
+    }
+
+    if !changes.is_empty() {
+        state
+            .settings()
+            .notifiers
+            .dispatch(notification().with_changes(changes));
+    }
+    if !pending_changes.is_empty() {
+        state.settings().notifiers.dispatch(
+            notification()
+                .with_outcome(Outcome::Pending)
+                .with_changes(pending_changes),
+        );
+    }
+
+    let message = if !pending.is_empty() {
+        "Service updated, some changes are waiting approval"
+    } else if !canaries.is_empty() {
+        "Canary rollout started"
+    } else {
+        "Service updated"
+    };
+    Ok(UpdateServiceResponse {
+        code: "200".to_string(),
+        transaction: transaction.to_string(),
+        message: message.to_string(),
+        args: vec![],
+        data: updated,
+        pending,
+        canary: canaries,
+    })
+}
 
-    let service_image_filter = format!("{}:", payload.image);
-    let services = state.docker.services_list().await.unwrap();
-    let mut services = services
+/// Names of the protected services of the request
+///
+/// The command line fails on them: its pending changes would be lost at exit.
+pub(crate) async fn protected_services(
+    state: &AppState,
+    payload: &UpdateServiceRequest,
+) -> Result<Vec<String>, APIError> {
+    let protected_label = &state.settings().config.protected_label;
+    Ok(
+        select_services(state.docker.services_list().await?, payload)?
+            .into_iter()
+            .filter(|service| service.label(protected_label) == Some("true"))
+            .map(|service| service.spec.name)
+            .collect(),
+    )
+}
+
+/// The services of the request: the named service or every service using the
+/// image
+///
+/// The options are validated against every service, so an invalid option
+/// fails the request before the first service is updated.
+fn select_services(
+    services: Vec<Service>,
+    payload: &UpdateServiceRequest,
+) -> Result<Vec<Service>, DockerError> {
+    let services = services
         .into_iter()
         .filter(|service| {
             if let Some(name) = &payload.service {
                 return service.spec.name == *name;
             }
-            service
-                .spec
-                .task_template
-                .container_spec
-                .image
-                .starts_with(&service_image_filter)
-        })
-        .map(|service| async {
-            info!("Updating service: {:?}", service);
-            service.update_image(&payload.image, &payload.tag).await;
-            service.into()
-            // ServiceResume::from(service)
+            split_image(&service.spec.task_template.container_spec.image).0 == payload.image
         })
-        .collect::<Vec<ServiceResume>>()
+        .collect::<Vec<Service>>();
+    for service in &services {
+        service.clone().apply_options(&payload.options)?;
+    }
+    Ok(services)
+}
+
+/// Prepare the update of the service to `image:tag` (or `image@digest`):
+/// pull the image on the nodes of the service when `prepull` is set, then run
+/// the `pre` hooks
+pub(crate) async fn before_update(
+    state: &AppState,
+    service: &Service,
+    image: &str,
+    tag: &str,
+    digest: Option<&str>,
+    prepull: bool,
+) -> Result<(), DockerError> {
+    if prepull {
+        let image_ref = match digest {
+            Some(digest) => format!("{}@{}", image, digest),
+            None => format!("{}:{}", image, tag),
+        };
+        prepull::run(
+            &state.docker,
+            state.settings().registry.docker_auth(image).as_deref(),
+            service,
+            &image_ref,
+            Duration::from_secs(state.settings().config.prepull_timeout),
+        )
+        .await?;
+    }
+    state
+        .settings()
+        .hooks
+        .before(&state.docker, service, image, tag)
+        .await
+}
+
+/// Time between the checks of the rollout, before putting its config back
+const ROLLOUT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// Time to wait for the rollout before giving up putting its config back
+const ROLLOUT_TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// Put back the update and rollback configs of the service once the rollout
+/// of the update changing them completed
+///
+/// A paused or rolled back rollout keeps the changed config (a rollback
+/// already returns to the previous spec, with the previous config).
+pub(crate) async fn restore_rollout_config(
+    state: Arc<AppState>,
+    service_id: String,
+    config: RolloutConfig,
+) {
+    let started_at = tokio::time::Instant::now();
+    loop {
+        let mut service = match state.docker.service_inspect(&service_id).await {
+            Ok(service) => service,
+            Err(e) => {
+                warn!("Rollout config of {} not restored: {}", service_id, e);
+                return;
+            }
+        };
+        // the status of the previous rollout, until the new one starts
+        let rollout = service
+            .update_status
+            .as_ref()
+            .filter(|status| status.started_at != config.previous_rollout)
+            .map(|status| status.state.as_str());
+        match rollout {
+            Some("completed") => {
+                let image = service.spec.task_template.container_spec.image.clone();
+                let _expected = state.history.expect(&service.id, &image);
+                service.spec.update_config = config.update_config;
+                service.spec.rollback_config = config.rollback_config;
+                match service.update().await {
+                    Ok(_) => info!("Rollout config of {} restored", service.spec.name),
+                    Err(e) => warn!(
+                        "Rollout config of {} not restored: {}",
+                        service.spec.name, e
+                    ),
+                }
+                return;
+            }
+            None | Some("updating") if started_at.elapsed() < ROLLOUT_TIMEOUT => {
+                tokio::time::sleep(ROLLOUT_POLL_INTERVAL).await
+            }
+            rollout => {
+                warn!(
+                    "Rollout config of {} not restored, the rollout is {}",
+                    service.spec.name,
+                    rollout.unwrap_or("not started")
+                );
+                return;
+            }
+        }
+    }
+}
+
+/// Update of a service waiting for its canary
+struct CanaryUpdate {
+    service: Service,
+    image: String,
+    tag: String,
+    update_options: ServiceUpdateOptions,
+    options: CanaryOptions,
+    prepull: bool,
+    actor: String,
+    transaction: Uuid,
+    request: serde_json::Value,
+}
+
+/// Run the canary of the service and promote the update when it is healthy
+async fn canary_update(state: Arc<AppState>, update: CanaryUpdate) {
+    let CanaryUpdate {
+        service,
+        image,
+        tag,
+        update_options,
+        options,
+        prepull,
+        actor,
+        transaction,
+        request,
+    } = update;
+    let started_at = Utc::now();
+    let name = service.spec.name.clone();
+    let entry = HistoryEntry::new(HistoryAction::Update, &service.id, &name, &actor)
+        .with_transaction(&transaction);
+    let change = ServiceChange::new(&service, &image, &tag);
+    let notification = || {
+        Notification::new(NotificationKind::Update, &transaction, &actor, started_at)
+            .with_request(&request)
+    };
+    let registry_auth = state.settings().registry.docker_auth(&image);
+    let canary = canary::run(
+        &state.docker,
+        registry_auth.as_deref(),
+        &service,
+        &image,
+        &tag,
+        &options,
+    );
+    if let Err(e) = canary.await {
+        warn!("Canary of {} failed, update aborted: {}", name, e);
+        state.history.record(entry.with_error(&e));
+        state.settings().notifiers.dispatch(
+            notification()
+                .with_changes(vec![change.with_error(&e)])
+                .with_error(&e),
+        );
+        return;
+    }
+    info!("Canary of {} healthy, promoting {}:{}", name, image, tag);
+    let mut from_image = None;
+    let mut rollout = None;
+    let _expected = state
+        .history
+        .expect(&service.id, &format!("{}:{}", image, tag));
+    let result = match state.docker.service_inspect(&service.id).await {
+        Ok(mut service) => {
+            from_image = Some(service.spec.task_template.container_spec.image.clone());
+            rollout = Some(service.rollout_config());
+            let result = match service.apply_options(&update_options) {
+                Ok(()) => before_update(&state, &service, &image, &tag, None, prepull).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => service.update_image(&image, &tag).await.map(|_| service),
+                Err(e) => Err(e),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    let entry = entry.with_images(from_image.as_deref(), Some(&format!("{}:{}", image, tag)));
+    match result {
+        Ok(service) => {
+            info!("Service {} updated to {}:{}", name, image, tag);
+            state.history.record(entry);
+            if let Some(rollout) = rollout.filter(|_| update_options.changes_rollout()) {
+                tokio::spawn(propagate(restore_rollout_config(
+                    state.clone(),
+                    service.id.clone(),
+                    rollout,
+                )));
+            }
+            state
+                .settings()
+                .hooks
+                .after(&state.docker, &service, &image, &tag)
+                .await;
+            state
+                .settings()
+                .notifiers
+                .dispatch(notification().with_changes(vec![change]));
+        }
+        Err(e) => {
+            warn!("Error updating service {}: {}", name, e);
+            state.history.record(entry.with_error(&e));
+            state.settings().notifiers.dispatch(
+                notification()
+                    .with_changes(vec![change.with_error(&e)])
+                    .with_error(&e),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn service(name: &str, image: &str, env: &[&str]) -> Service {
+        serde_json::from_value(json!({
+            "ID": name,
+            "Version": { "Index": 1 },
+            "CreatedAt": "2024-06-01T10:00:00Z",
+            "UpdatedAt": "2024-06-01T10:00:00Z",
+            "Spec": {
+                "Name": name,
+                "TaskTemplate": { "ContainerSpec": { "Image": image, "Env": env } }
+            }
+        }))
+        .unwrap()
+    }
+
+    fn request(unset: &[&str]) -> UpdateServiceRequest {
+        serde_json::from_value(json!({
+            "image": "shop/api",
+            "tag": "2.0",
+            "env": { "unset": unset },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_select_services() {
+        let services = vec![
+            service("shop_api", "shop/api:1.0", &["DEBUG=1"]),
+            service("shop_worker", "shop/api:1.0", &["DEBUG=1"]),
+            service("shop_web", "shop/web:1.0", &[]),
+            service("shop_cron", "shop/api@sha256:abcd", &["DEBUG=1"]),
+        ];
+        let selected = select_services(services.clone(), &request(&["DEBUG"])).unwrap();
+        let names = selected
+            .iter()
+            .map(|service| service.spec.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, vec!["shop_api", "shop_worker", "shop_cron"]);
+        // the options are not applied, only validated
+        assert_eq!(
+            selected[0].spec.task_template.container_spec.env,
+            Some(vec!["DEBUG=1".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_select_services_invalid_option() {
+        // the second service can't unset DEBUG: nothing is updated
+        let services = vec![
+            service("shop_api", "shop/api:1.0", &["DEBUG=1"]),
+            service("shop_worker", "shop/api:1.0", &[]),
+        ];
+        assert!(matches!(
+            select_services(services, &request(&["DEBUG"])),
+            Err(DockerError::InvalidChange(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_pre_hook_keeps_no_pending_change() {
+        use crate::config::Config;
+        use axum::{
+            http::StatusCode,
+            routing::{get, post},
+            Json, Router,
+        };
+        let mut protected = service("shop_api", "shop/api:1.0", &[]);
+        protected.spec.labels = Some([("updater.protected".into(), "true".into())].into());
+        let services = vec![protected, service("shop_worker", "shop/api:1.0", &[])];
+        let app = Router::new()
+            .route("/services", get(move || async move { Json(services) }))
+            .route(
+                "/hook",
+                post(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let hooks = serde_json::from_value(json!([{
+            "name": "migrate",
+            "stage": "pre",
+            "services": ["shop_worker"],
+            "type": "http",
+            "url": format!("{}/hook", url),
+        }]))
+        .unwrap();
+        let state = Arc::new(AppState::new(Config {
+            docker_url: url,
+            hooks,
+            ..Config::default()
+        }));
+
+        let result = update(
+            &state,
+            &Token::local("ci"),
+            &request(&[]),
+            &Progress::none(),
+        )
         .await;
+        assert_eq!(result.unwrap_err().code, "hook_failed");
+        assert!(state.approvals.list().is_empty());
+    }
 
-    Json(UpdateServiceResponse {
-        code: "200".to_string(),
-        transaction: transaction.to_string(),
-        message: "Service updated".to_string(),
-        args: vec![],
-        data: services
-            .into_iter()
-            .map(|service| service.into())
-            .collect::<Vec<ServiceResume>>(),
-    })
+    #[tokio::test]
+    async fn test_restore_rollout_config() {
+        use crate::config::Config;
+        use axum::{
+            extract::State,
+            routing::{get, post},
+            Json, Router,
+        };
+        use std::sync::Mutex;
+        let updated = Arc::new(Mutex::new(None::<serde_json::Value>));
+        let app = Router::new()
+            .route(
+                "/services/:id",
+                get(|| async {
+                    Json(json!({
+                        "ID": "shop_api",
+                        "Version": { "Index": 3 },
+                        "CreatedAt": "2024-06-01T10:00:00Z",
+                        "UpdatedAt": "2024-06-02T10:00:00Z",
+                        "Spec": {
+                            "Name": "shop_api",
+                            "TaskTemplate": { "ContainerSpec": { "Image": "shop/api:2.0" } },
+                            "UpdateConfig": { "Parallelism": 5, "Order": "start-first" }
+                        },
+                        "UpdateStatus": {
+                            "State": "completed",
+                            "StartedAt": "2024-06-02T10:00:00Z"
+                        }
+                    }))
+                }),
+            )
+            .route(
+                "/services/:id/update",
+                post(
+                    |State(updated): State<Arc<Mutex<Option<serde_json::Value>>>>,
+                     Json(spec): Json<serde_json::Value>| async move {
+                        *updated.lock().unwrap() = Some(spec);
+                    },
+                ),
+            )
+            .with_state(updated.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let state = Arc::new(AppState::new(Config {
+            docker_url: url,
+            ..Config::default()
+        }));
+
+        let mut service = service("shop_api", "shop/api:1.0", &[]);
+        service.spec.update_config = Some(Default::default());
+        service.spec.update_config.as_mut().unwrap().parallelism = Some(1);
+        let rollout = service.rollout_config();
+        restore_rollout_config(state, "shop_api".into(), rollout).await;
+        let spec = updated.lock().unwrap().take().unwrap();
+        assert_eq!(spec["UpdateConfig"], json!({ "Parallelism": 1 }));
+        assert!(spec.get("RollbackConfig").is_none());
+        assert_eq!(
+            spec["TaskTemplate"]["ContainerSpec"]["Image"],
+            "shop/api:2.0"
+        );
+    }
 }
@@ -7,6 +7,10 @@
 //!
 //! For configure the application, please follow the instructions in [config::Config](config/struct.Config.html).
 //!
+//! # Command line
+//!
+//! The same binary updates, rolls back and lists the services from a shell, see [cli](cli/index.html).
+//!
 //! # Development
 //!
 //! You need convert the unix socket from docker to http. You can use the [socat](http://www.dest-unreach.org/socat/) for this.
@@ -15,13 +19,20 @@
 //! socat TCP-LISTEN:8080,bind=127.0.0.1,reuseaddr,fork,range=127.0.0.0/8 UNIX-CLIENT:/var/run/docker.sock
 //! ```
 //!
-use std::{sync::Arc, time::Duration};
+use std::{
+    net::SocketAddr,
+    process::ExitCode,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use axum::{
     http::Method,
+    middleware,
     routing::{get, post},
     Router,
 };
+use clap::Parser;
 use tokio::signal;
 use tower_http::{
     cors::{AllowOrigin, CorsLayer},
@@ -29,62 +40,241 @@ use tower_http::{
     timeout::TimeoutLayer,
     trace::TraceLayer,
 };
-use tracing::info;
-use tracing_subscriber::FmtSubscriber;
+use tracing::{info, warn};
+use tracing_subscriber::{
+    filter::LevelFilter, fmt::writer::BoxMakeWriter, layer::SubscriberExt, Layer,
+};
 
+mod build_info;
+mod cli;
+mod client;
 mod config;
 mod controllers;
+mod reload;
 mod services;
+#[cfg(feature = "otel")]
+mod telemetry;
+
+/// Time to wait for the version of the docker daemon
+const DOCKER_VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time between the calls while the daemon doesn't answer
+const DOCKER_VERSION_RETRY: Duration = Duration::from_secs(30);
 
 struct AppState {
+    /// Replaced as a whole when the configuration is reloaded
+    settings: RwLock<Arc<Settings>>,
     docker: services::docker::Docker,
+    approvals: services::approvals::Approvals,
+    background: services::background::BackgroundJobs,
+    history: services::history::History,
+    /// Discovered in background from the startup, see [AppState::discover_docker_version]
+    docker_version: std::sync::OnceLock<services::docker::types::DockerVersion>,
+}
+
+/// The configuration and the services built from it
+struct Settings {
+    config: config::Config,
+    registry: services::registry::Registry,
+    notifiers: services::notify::Notifiers,
+    hooks: services::hooks::Hooks,
+    redactor: services::redact::Redactor,
+}
+
+impl Settings {
+    fn new(config: config::Config) -> Self {
+        Settings {
+            registry: config.registry(),
+            notifiers: config.notifiers(),
+            hooks: services::hooks::Hooks::new(config.hooks.clone()),
+            redactor: config.redactor(),
+            config,
+        }
+    }
+}
+
+impl AppState {
+    fn new(config: config::Config) -> Self {
+        AppState {
+            docker: services::docker::DockerBuilder::builder()
+                .with_http_url(&config.docker_url)
+                .with_cache_ttl(Duration::from_secs(config.services_cache_ttl))
+                .build(),
+            approvals: services::approvals::Approvals::new(),
+            background: services::background::BackgroundJobs::new(),
+            history: services::history::History::new(
+                config.history_size,
+                config.history_file.as_deref().map(std::path::Path::new),
+            ),
+            docker_version: std::sync::OnceLock::new(),
+            settings: RwLock::new(Arc::new(Settings::new(config))),
+        }
+    }
+
+    /// The current settings, kept by the caller even if a reload happens
+    fn settings(&self) -> Arc<Settings> {
+        self.settings.read().unwrap().clone()
+    }
+
+    /// The version of the docker daemon, once discovered
+    fn docker_version(&self) -> Option<&services::docker::types::DockerVersion> {
+        self.docker_version.get()
+    }
+
+    /// Ask the version of the docker daemon until it answers, each call with a
+    /// short timeout: the requests only read the discovered version
+    async fn discover_docker_version(&self) {
+        loop {
+            match tokio::time::timeout(DOCKER_VERSION_TIMEOUT, self.docker.version()).await {
+                Ok(Ok(version)) => {
+                    info!(
+                        "Docker daemon {} (api {})",
+                        version.version, version.api_version
+                    );
+                    let _ = self.docker_version.set(version);
+                    return;
+                }
+                Ok(Err(e)) => warn!("Error getting the docker version: {}", e),
+                Err(_) => warn!("Error getting the docker version: no answer in time"),
+            }
+            tokio::time::sleep(DOCKER_VERSION_RETRY).await;
+        }
+    }
 }
 
 /// Main entrypoint for the application
 #[tokio::main]
-async fn main() -> Result<(), anyhow::Error> {
+async fn main() -> Result<ExitCode, anyhow::Error> {
+    let cli = cli::Cli::parse();
+    match cli.command {
+        Some(cli::Command::Config {
+            command: cli::ConfigCommand::Check,
+        }) => return Ok(cli::config_check()),
+        // the client doesn't need the configuration of the server
+        Some(cli::Command::Client {
+            url,
+            token,
+            timeout,
+            command,
+        }) => {
+            let client =
+                client::Client::new(&url, &token).with_timeout(Duration::from_secs(timeout));
+            return Ok(client.run(command).await);
+        }
+        _ => {}
+    }
     let config = config::Config::load();
 
-    // config log_level
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(config.log_level())
-        .finish();
+    // config log_level and log_format, the commands keep stdout for their output
+    let writer = match cli.command {
+        None | Some(cli::Command::Serve) => BoxMakeWriter::new(std::io::stdout),
+        Some(_) => BoxMakeWriter::new(std::io::stderr),
+    };
+    let logs = tracing_subscriber::fmt::layer().with_writer(writer);
+    let logs = match config.log_format.as_str() {
+        "json" => logs
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+        _ => logs.boxed(),
+    };
+    let subscriber = tracing_subscriber::registry()
+        .with(logs.with_filter(LevelFilter::from_level(config.log_level())));
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(telemetry::layer(&config).with_filter(LevelFilter::INFO));
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    #[cfg(not(feature = "otel"))]
+    if config.otel_endpoint.is_some() {
+        tracing::warn!("otel_endpoint is ignored, the updater is built without the otel feature");
+    }
 
-    // create our application state
-    let app_state = Arc::new(AppState {
-        docker: services::docker::DockerBuilder::builder()
-            .with_http_url(&config.docker_url)
-            .build(),
-    });
+    let app_state = Arc::new(AppState::new(config));
+    let code = match cli.command {
+        None | Some(cli::Command::Serve) => {
+            serve(app_state).await?;
+            ExitCode::SUCCESS
+        }
+        Some(command) => cli::run(command, app_state).await,
+    };
+    #[cfg(feature = "otel")]
+    telemetry::shutdown();
+    Ok(code)
+}
+
+/// Run the api server until a shutdown signal
+async fn serve(app_state: Arc<AppState>) -> Result<(), anyhow::Error> {
+    let settings = app_state.settings();
+    let config = &settings.config;
+    let server_addr = format!("{}:{}", config.host, config.port);
+    let http_request_timeout = config.http_request_timeout;
+    let http_body_limit = config.http_body_limit;
+    let graceful_shutdown_timeout = config.graceful_shutdown_timeout;
+
+    if config.docker_events {
+        let state = app_state.clone();
+        tokio::spawn(async move { services::watcher::watch(&state.docker, &state.history).await });
+    }
+    tokio::spawn(reload::watch(app_state.clone()));
+    let state = app_state.clone();
+    tokio::spawn(async move { state.discover_docker_version().await });
 
     // build our application
     let app = Router::new()
         .route("/", get(controllers::echo::get_root))
+        .route("/version", get(controllers::echo::get_version))
+        .route("/healthz", get(controllers::health::healthz))
+        .route("/readyz", get(controllers::health::readyz))
         .route("/update", post(controllers::update::update_service))
-        .layer(TimeoutLayer::new(Duration::from_secs(
-            config.http_request_timeout,
-        )))
-        .layer(RequestBodyLimitLayer::new(config.http_body_limit))
+        .route("/services", get(controllers::services::list_services))
+        .route("/services/:name", get(controllers::services::get_service))
+        .route(
+            "/services/:name/tasks",
+            get(controllers::services::list_tasks),
+        )
+        .route(
+            "/services/:name/logs",
+            get(controllers::services::service_logs),
+        )
+        .route("/releases", post(controllers::releases::create_release))
+        .route("/rollback", post(controllers::rollback::rollback_service))
+        .route("/history", get(controllers::history::list_history))
+        .route("/jobs/run", post(controllers::jobs::run_job))
+        .route("/jobs/:id", get(controllers::jobs::get_job))
+        .route("/jobs/:id/events", get(controllers::jobs::job_events))
+        .route("/approvals", get(controllers::approvals::list_pending))
+        .route(
+            "/approvals/:id/approve",
+            post(controllers::approvals::approve),
+        )
+        .route(
+            "/approvals/:id/reject",
+            post(controllers::approvals::reject),
+        )
+        .layer(TimeoutLayer::new(Duration::from_secs(http_request_timeout)))
+        .layer(RequestBodyLimitLayer::new(http_body_limit))
         .layer((
             TraceLayer::new_for_http(),
-            TimeoutLayer::new(Duration::from_secs(config.graceful_shutdown_timeout)),
+            TimeoutLayer::new(Duration::from_secs(graceful_shutdown_timeout)),
         ))
         .layer(
             CorsLayer::new()
                 .allow_origin(AllowOrigin::any())
-                .allow_methods([Method::GET, Method::POST, Method::OPTIONS]),
+                .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+                .expose_headers([controllers::request::REQUEST_ID_HEADER]),
         )
+        .layer(middleware::from_fn(controllers::request::request_span))
         .with_state(app_state);
 
     // run our app with hyper, listening globally on port 3000
-    let server_addr = format!("{}:{}", config.host, config.port);
     let listener = tokio::net::TcpListener::bind(&server_addr).await.unwrap();
     info!("Starting server: {}", server_addr);
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await
-        .unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await
+    .unwrap();
 
     Ok(())
 }
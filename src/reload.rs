@@ -0,0 +1,199 @@
+//! # Reload of the configuration
+//!
+//! The server reloads the configuration when a file of `CONFIG_PATH` changes
+//! (checked every `reload_interval` seconds) or on `SIGHUP`. The tokens,
+//! registries, notifications, hooks and policies of the new configuration
+//! replace the current ones at once. An invalid configuration is rejected, the
+//! current one is kept.
+//!
+//! The settings of the listener and of the services started with the server
+//! ([FIXED_KEYS]) keep their value until a restart.
+//!
+use std::{sync::Arc, time::Duration};
+
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::{config::Config, AppState, Settings};
+
+/// Declare the keys of the configuration only read at startup: their names
+/// and the copy of their current value in a reloaded configuration
+macro_rules! fixed_keys {
+    ($($key:ident),* $(,)?) => {
+        /// Keys of the configuration only read at startup
+        pub const FIXED_KEYS: &[&str] = &[$(stringify!($key)),*];
+
+        /// Copy the fixed keys of the current configuration in the new one
+        fn keep_fixed(current: &Config, config: &mut Config) {
+            $(config.$key = current.$key.clone();)*
+        }
+    };
+}
+
+fixed_keys!(
+    log_level,
+    log_format,
+    host,
+    port,
+    docker_url,
+    graceful_shutdown_timeout,
+    http_body_limit,
+    http_request_timeout,
+    docker_events,
+    services_cache_ttl,
+    history_size,
+    history_file,
+    reload_interval,
+    otel_endpoint,
+    otel_service_name,
+);
+
+/// Reload the configuration when its files change or on `SIGHUP`
+pub async fn watch(state: Arc<AppState>) {
+    let interval = state.settings().config.reload_interval;
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval.max(1)));
+    let mut modified = Config::modified();
+    let mut hangup = hangup_signal();
+    loop {
+        tokio::select! {
+            _ = ticker.tick(), if interval > 0 => {
+                let current = Config::modified();
+                if current == modified {
+                    continue;
+                }
+                modified = current;
+                info!("Configuration files changed, reloading");
+            }
+            _ = hangup_received(&mut hangup) => {
+                info!("SIGHUP received, reloading the configuration");
+            }
+        }
+        reload(&state);
+    }
+}
+
+/// Replace the settings with the configuration read again
+pub fn reload(state: &AppState) {
+    let config = match Config::extract() {
+        Ok(config) => config,
+        Err(errors) => {
+            warn!("Configuration reload rejected:\n{}", errors);
+            return;
+        }
+    };
+    let current = state.settings();
+    let (config, changed, ignored) = merge(&current.config, config);
+    for key in ignored {
+        warn!("{} changed, the new value needs a restart", key);
+    }
+    if changed.is_empty() {
+        info!("Configuration reloaded, nothing changed");
+        return;
+    }
+    *state.settings.write().unwrap() = Arc::new(Settings::new(config));
+    info!("Configuration reloaded, changed: {}", changed.join(", "));
+}
+
+/// Keep the fixed keys of the current configuration in the new one
+///
+/// Returns the configuration to apply, the keys it changes (the names of the
+/// changed entries for the maps, like `tokens.github`) and the fixed keys
+/// whose change is ignored.
+fn merge(current: &Config, mut config: Config) -> (Config, Vec<String>, Vec<String>) {
+    let before = serde_json::to_value(current).unwrap_or_default();
+    let after = serde_json::to_value(&config).unwrap_or_default();
+    let mut changed = vec![];
+    let mut ignored = vec![];
+    if let (Value::Object(before), Value::Object(after)) = (&before, &after) {
+        for (key, value) in after {
+            if before.get(key) == Some(value) {
+                continue;
+            }
+            if FIXED_KEYS.contains(&key.as_str()) {
+                ignored.push(key.clone());
+            } else if let (Some(Value::Object(old)), Value::Object(new)) = (before.get(key), value)
+            {
+                let mut names = old
+                    .keys()
+                    .chain(new.keys())
+                    .filter(|name| old.get(*name) != new.get(*name))
+                    .map(|name| format!("{}.{}", key, name))
+                    .collect::<Vec<String>>();
+                names.sort();
+                names.dedup();
+                changed.extend(names);
+            } else {
+                changed.push(key.clone());
+            }
+        }
+    }
+    keep_fixed(current, &mut config);
+    (config, changed, ignored)
+}
+
+#[cfg(unix)]
+type Hangup = tokio::signal::unix::Signal;
+#[cfg(not(unix))]
+type Hangup = ();
+
+#[cfg(unix)]
+fn hangup_signal() -> Hangup {
+    tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+        .expect("failed to install SIGHUP handler")
+}
+
+#[cfg(not(unix))]
+fn hangup_signal() -> Hangup {}
+
+#[cfg(unix)]
+async fn hangup_received(hangup: &mut Hangup) {
+    hangup.recv().await;
+}
+
+#[cfg(not(unix))]
+async fn hangup_received(_: &mut Hangup) {
+    std::future::pending::<()>().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigToken;
+
+    #[test]
+    fn test_merge() {
+        let mut current = Config::default();
+        current.tokens.insert(
+            "github".into(),
+            ConfigToken::Secret("a-long-random-secret".into()),
+        );
+        let mut config = Config {
+            port: 4000,
+            canary_timeout: 600,
+            ..Config::default()
+        };
+        config.tokens.insert(
+            "github".into(),
+            ConfigToken::Secret("a-rotated-random-secret".into()),
+        );
+        config.tokens.insert(
+            "ops".into(),
+            ConfigToken::Secret("another-random-secret".into()),
+        );
+        let (config, changed, ignored) = merge(&current, config);
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.canary_timeout, 600);
+        assert_eq!(config.tokens["github"].secret(), "a-rotated-random-secret");
+        assert_eq!(ignored, vec!["port"]);
+        let mut changed = changed;
+        changed.sort();
+        assert_eq!(
+            changed,
+            vec!["canary_timeout", "tokens.github", "tokens.ops"]
+        );
+
+        let (_, changed, ignored) = merge(&current, Config::default());
+        assert_eq!(changed, vec!["tokens.github"]);
+        assert!(ignored.is_empty());
+    }
+}
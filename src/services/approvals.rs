@@ -0,0 +1,106 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::docker::types::ServiceUpdateOptions;
+
+/// A change requested for a protected service, waiting for an approver
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingChange {
+    pub id: Uuid,
+    #[serde(rename = "serviceId")]
+    pub service_id: String,
+    #[serde(rename = "serviceName")]
+    pub service_name: String,
+    pub image: String,
+    pub tag: String,
+    pub options: ServiceUpdateOptions,
+    #[serde(rename = "requestedBy")]
+    pub requested_by: String,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+}
+
+/// In-memory store of the pending changes
+#[derive(Default)]
+pub struct Approvals {
+    pending: Mutex<HashMap<Uuid, PendingChange>>,
+}
+
+impl Approvals {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a new pending change and return it
+    pub fn add(
+        &self,
+        service_id: &str,
+        service_name: &str,
+        image: &str,
+        tag: &str,
+        options: &ServiceUpdateOptions,
+        requested_by: &str,
+    ) -> PendingChange {
+        let change = PendingChange {
+            id: Uuid::new_v4(),
+            service_id: service_id.to_owned(),
+            service_name: service_name.to_owned(),
+            image: image.to_owned(),
+            tag: tag.to_owned(),
+            options: options.clone(),
+            requested_by: requested_by.to_owned(),
+            created_at: Utc::now(),
+        };
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(change.id, change.clone());
+        change
+    }
+
+    /// List the pending changes, oldest first
+    pub fn list(&self) -> Vec<PendingChange> {
+        let mut changes: Vec<PendingChange> =
+            self.pending.lock().unwrap().values().cloned().collect();
+        changes.sort_by_key(|change| change.created_at);
+        changes
+    }
+
+    /// Remove a pending change, returning it when it exists
+    pub fn take(&self, id: &Uuid) -> Option<PendingChange> {
+        self.pending.lock().unwrap().remove(id)
+    }
+
+    /// Put back a change taken by an approval that failed, to be retried
+    pub fn restore(&self, change: PendingChange) {
+        self.pending.lock().unwrap().insert(change.id, change);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_take_pending_change() {
+        let approvals = Approvals::new();
+        let change = approvals.add(
+            "abc",
+            "web",
+            "nginx",
+            "1.27",
+            &ServiceUpdateOptions::default(),
+            "github",
+        );
+        assert_eq!(approvals.list().len(), 1);
+        let taken = approvals.take(&change.id).unwrap();
+        assert_eq!(taken.service_name, "web");
+        assert!(approvals.take(&change.id).is_none());
+        assert!(approvals.list().is_empty());
+        approvals.restore(taken);
+        assert_eq!(approvals.list()[0].id, change.id);
+    }
+}
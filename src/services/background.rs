@@ -0,0 +1,227 @@
+//! # Background jobs
+//!
+//! An update or a rollback requested with `"background": true` runs after the
+//! response, which only has the id of its job. The progress of the job is kept
+//! in memory as events, read with `GET /jobs/{id}` or followed with
+//! `GET /jobs/{id}/events` until the job finishes.
+//!
+use std::{collections::VecDeque, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Number of finished jobs kept in memory
+const MAX_FINISHED: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// A step of a background job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobEvent {
+    pub time: DateTime<Utc>,
+    pub state: JobState,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    pub message: String,
+}
+
+impl JobEvent {
+    /// The last event of a job
+    pub fn is_final(&self) -> bool {
+        self.state != JobState::Running
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackgroundJob {
+    pub id: Uuid,
+    /// `update` or `rollback`
+    pub kind: String,
+    pub actor: String,
+    pub state: JobState,
+    #[serde(rename = "createdAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "finishedAt")]
+    pub finished_at: Option<DateTime<Utc>>,
+    pub events: Vec<JobEvent>,
+    /// Response of the request, or its error, once finished
+    pub result: Option<Value>,
+}
+
+/// In-memory store of the background jobs
+pub struct BackgroundJobs {
+    jobs: Mutex<VecDeque<BackgroundJob>>,
+    events: broadcast::Sender<(Uuid, JobEvent)>,
+}
+
+impl Default for BackgroundJobs {
+    fn default() -> Self {
+        Self {
+            jobs: Mutex::new(VecDeque::new()),
+            events: broadcast::channel(256).0,
+        }
+    }
+}
+
+impl BackgroundJobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create a running job
+    pub fn start(&self, kind: &str, actor: &str) -> BackgroundJob {
+        let job = BackgroundJob {
+            id: Uuid::new_v4(),
+            kind: kind.to_owned(),
+            actor: actor.to_owned(),
+            state: JobState::Running,
+            created_at: Utc::now(),
+            finished_at: None,
+            events: vec![],
+            result: None,
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.push_back(job.clone());
+        let finished = jobs
+            .iter()
+            .filter(|job| job.state != JobState::Running)
+            .count();
+        if finished > MAX_FINISHED {
+            if let Some(position) = jobs.iter().position(|job| job.state != JobState::Running) {
+                jobs.remove(position);
+            }
+        }
+        job
+    }
+
+    pub fn get(&self, id: &Uuid) -> Option<BackgroundJob> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|job| job.id == *id)
+            .cloned()
+    }
+
+    /// The job with the receiver of its next events
+    pub fn follow(
+        &self,
+        id: &Uuid,
+    ) -> Option<(BackgroundJob, broadcast::Receiver<(Uuid, JobEvent)>)> {
+        // subscribe while locked: no event is missed or received twice
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.iter().find(|job| job.id == *id)?.clone();
+        Some((job, self.events.subscribe()))
+    }
+
+    /// Progress of the job, to record its events
+    pub fn progress(&self, id: Uuid) -> Progress<'_> {
+        Progress {
+            job: Some((self, id)),
+        }
+    }
+
+    fn record(&self, id: Uuid, event: JobEvent, result: Option<Value>) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let Some(job) = jobs.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        if event.is_final() {
+            job.state = event.state;
+            job.finished_at = Some(event.time);
+            job.result = result;
+        }
+        job.events.push(event.clone());
+        // no receiver is not an error
+        let _ = self.events.send((id, event));
+    }
+}
+
+/// Record the progress of a job, or nothing for the requests waiting their response
+pub struct Progress<'a> {
+    job: Option<(&'a BackgroundJobs, Uuid)>,
+}
+
+impl Progress<'_> {
+    pub fn none() -> Self {
+        Self { job: None }
+    }
+
+    /// A step of the job, optionally of a service
+    pub fn event(&self, service: Option<&str>, message: &str) {
+        self.send(JobState::Running, service, message, None);
+    }
+
+    /// The end of the job, with the response of the request
+    pub fn finish(&self, succeeded: bool, message: &str, result: Value) {
+        let state = if succeeded {
+            JobState::Succeeded
+        } else {
+            JobState::Failed
+        };
+        self.send(state, None, message, Some(result));
+    }
+
+    fn send(&self, state: JobState, service: Option<&str>, message: &str, result: Option<Value>) {
+        if let Some((jobs, id)) = self.job {
+            let event = JobEvent {
+                time: Utc::now(),
+                state,
+                service: service.map(str::to_owned),
+                message: message.to_owned(),
+            };
+            jobs.record(id, event, result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_background_jobs() {
+        let jobs = BackgroundJobs::new();
+        let job = jobs.start("update", "github");
+        let (_, mut receiver) = jobs.follow(&job.id).unwrap();
+        let progress = jobs.progress(job.id);
+        progress.event(Some("shop_api"), "Updating to shop/api:2.0");
+        progress.finish(true, "Service updated", json!({ "code": "200" }));
+        Progress::none().event(None, "ignored");
+
+        let job = jobs.get(&job.id).unwrap();
+        assert_eq!(job.state, JobState::Succeeded);
+        assert!(job.finished_at.is_some());
+        assert_eq!(job.events.len(), 2);
+        assert_eq!(job.events[0].service.as_deref(), Some("shop_api"));
+        assert_eq!(job.result, Some(json!({ "code": "200" })));
+        let (id, event) = receiver.try_recv().unwrap();
+        assert_eq!(id, job.id);
+        assert!(!event.is_final());
+        assert!(receiver.try_recv().unwrap().1.is_final());
+        assert!(jobs.get(&Uuid::new_v4()).is_none());
+    }
+
+    #[test]
+    fn test_max_finished() {
+        let jobs = BackgroundJobs::new();
+        let running = jobs.start("update", "github");
+        for _ in 0..=MAX_FINISHED {
+            let job = jobs.start("update", "github");
+            jobs.progress(job.id).finish(true, "done", Value::Null);
+        }
+        jobs.start("rollback", "github");
+        assert!(jobs.get(&running.id).is_some());
+        assert_eq!(jobs.jobs.lock().unwrap().len(), MAX_FINISHED + 2);
+    }
+}
@@ -0,0 +1,179 @@
+//! # Canary rollout
+//!
+//! Before updating a replicated service, a temporary `<name>-canary` service is
+//! created with the new image and a small replica count. It shares the networks
+//! and labels of the main service, but doesn't publish any port. When its tasks
+//! stay running for the configured period, the canary is considered healthy.
+//!
+//! The canary pulls the new image with the credentials of its registry, and is
+//! removed even when its task is dropped.
+//!
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant};
+use tracing::info;
+
+use super::docker::{
+    error::DockerError,
+    types::{Service, ServiceSpec, ServiceSpecMode, ServiceSpecModeReplicated},
+    Docker, STACK_IMAGE_LABEL,
+};
+
+/// Label added to the canary service, with the name of the main service
+pub const CANARY_LABEL: &str = "updater.canary";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone)]
+pub struct CanaryOptions {
+    /// Number of replicas of the canary service
+    pub replicas: u64,
+    /// Time (in seconds) all canary tasks must stay running
+    pub healthy_period: u64,
+    /// Time (in seconds) to wait for the canary before giving up
+    pub timeout: u64,
+}
+
+/// Build the spec of the canary service for `service` running `image:tag`
+pub fn canary_spec(
+    service: &Service,
+    image: &str,
+    tag: &str,
+    replicas: u64,
+) -> Result<ServiceSpec, DockerError> {
+    let replicated = service
+        .spec
+        .mode
+        .as_ref()
+        .and_then(|mode| mode.replicated.as_ref())
+        .is_some();
+    if !replicated {
+        return Err(DockerError::CanaryError(format!(
+            "Service {} is not replicated",
+            service.spec.name
+        )));
+    }
+    let mut spec = service.spec.clone();
+    spec.name = format!("{}-canary", service.spec.name);
+    spec.mode = Some(ServiceSpecMode {
+        replicated: Some(ServiceSpecModeReplicated { replicas }),
+        ..Default::default()
+    });
+    spec.endpoint_spec = None;
+    spec.task_template.container_spec.image = format!("{}:{}", image, tag);
+    let labels = spec.labels.get_or_insert_with(Default::default);
+    if let Some(label) = labels.get_mut(STACK_IMAGE_LABEL) {
+        *label = spec.task_template.container_spec.image.clone();
+    }
+    labels.insert(CANARY_LABEL.into(), service.spec.name.clone());
+    Ok(spec)
+}
+
+/// Run a canary of `service` with `image:tag`
+///
+/// `registry_auth` is the `X-Registry-Auth` of the private registries. The
+/// canary service is always removed, also when the future is dropped. An error
+/// is returned when its tasks didn't stay healthy for the configured period
+/// before the timeout.
+pub async fn run(
+    docker: &Docker,
+    registry_auth: Option<&str>,
+    service: &Service,
+    image: &str,
+    tag: &str,
+    options: &CanaryOptions,
+) -> Result<(), DockerError> {
+    let spec = canary_spec(service, image, tag, options.replicas)?;
+    let canary = docker
+        .service_create_temporary(&spec, registry_auth)
+        .await?;
+    info!("Canary {} created for {}:{}", spec.name, image, tag);
+    let result = wait_healthy(docker, &canary.id, options).await;
+    canary.remove().await;
+    result
+}
+
+async fn wait_healthy(
+    docker: &Docker,
+    id: &str,
+    options: &CanaryOptions,
+) -> Result<(), DockerError> {
+    let started_at = Instant::now();
+    let timeout = Duration::from_secs(options.timeout);
+    let healthy_period = Duration::from_secs(options.healthy_period);
+    let mut healthy_since: Option<Instant> = None;
+    loop {
+        let status = docker.service_status(id).await?;
+        if status.desired_tasks > 0 && status.running_tasks >= status.desired_tasks {
+            let since = *healthy_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= healthy_period {
+                return Ok(());
+            }
+        } else {
+            healthy_since = None;
+        }
+        if started_at.elapsed() >= timeout {
+            return Err(DockerError::CanaryError(format!(
+                "Canary not healthy after {}s: {}/{} tasks running",
+                options.timeout, status.running_tasks, status.desired_tasks
+            )));
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn service(mode: serde_json::Value) -> Service {
+        serde_json::from_value(json!({
+            "ID": "abc",
+            "Version": { "Index": 10 },
+            "CreatedAt": "2024-06-01T10:00:00Z",
+            "UpdatedAt": "2024-06-01T10:00:00Z",
+            "Spec": {
+                "Name": "web",
+                "Labels": {
+                    "com.docker.stack.namespace": "shop",
+                    "com.docker.stack.image": "nginx:1.26"
+                },
+                "TaskTemplate": {
+                    "ContainerSpec": { "Image": "nginx:1.26" },
+                    "Networks": [{ "Target": "net" }]
+                },
+                "Mode": mode,
+                "EndpointSpec": {
+                    "Ports": [{ "Protocol": "tcp", "TargetPort": 80, "PublishedPort": 80 }]
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_canary_spec() {
+        let spec = canary_spec(
+            &service(json!({ "Replicated": { "Replicas": 4 } })),
+            "nginx",
+            "1.27",
+            1,
+        )
+        .unwrap();
+        assert_eq!(spec.name, "web-canary");
+        assert_eq!(spec.task_template.container_spec.image, "nginx:1.27");
+        assert_eq!(spec.mode.unwrap().replicated.unwrap().replicas, 1);
+        assert!(spec.endpoint_spec.is_none());
+        assert_eq!(spec.task_template.networks.unwrap()[0].target, "net");
+        let labels = spec.labels.unwrap();
+        assert_eq!(labels.get(CANARY_LABEL).unwrap(), "web");
+        assert_eq!(labels.get("com.docker.stack.namespace").unwrap(), "shop");
+        assert_eq!(labels.get(STACK_IMAGE_LABEL).unwrap(), "nginx:1.27");
+    }
+
+    #[test]
+    fn test_canary_spec_global_service() {
+        assert!(canary_spec(&service(json!({ "Global": {} })), "nginx", "1.27", 1).is_err());
+    }
+}
@@ -8,4 +8,18 @@ pub enum DockerError {
     ParsingError(#[from] serde_json::Error),
     #[error("Service update error: {0}")]
     ServiceUpdateError(String),
+    #[error("Service create error: {0}")]
+    ServiceCreateError(String),
+    #[error("Service delete error: {0}")]
+    ServiceDeleteError(String),
+    #[error("Canary error: {0}")]
+    CanaryError(String),
+    #[error("Job error: {0}")]
+    JobError(String),
+    #[error("Hook error: {0}")]
+    HookError(String),
+    #[error("Invalid change: {0}")]
+    InvalidChange(String),
+    #[error("Service not found: {0}")]
+    ServiceNotFound(String),
 }
@@ -0,0 +1,91 @@
+//! # Docker events
+//!
+//! The `/events` endpoint streams one json object per line.
+//!
+use std::collections::HashMap;
+
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use super::error::DockerError;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventActor {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(default)]
+    #[serde(rename = "Attributes")]
+    pub attributes: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DockerEvent {
+    #[serde(rename = "Type")]
+    pub r#type: String,
+    #[serde(rename = "Action")]
+    pub action: String,
+    #[serde(rename = "Actor")]
+    pub actor: EventActor,
+    #[serde(rename = "time")]
+    pub time: i64,
+}
+
+impl DockerEvent {
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.actor.attributes.get(key).map(|value| value.as_str())
+    }
+}
+
+/// Split the body of the events response in events
+#[derive(Debug, Default)]
+pub struct EventDecoder {
+    buffer: Vec<u8>,
+}
+
+impl EventDecoder {
+    /// Add the bytes received, returning the complete events
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<Result<DockerEvent, DockerError>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = vec![];
+        while let Some(position) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=position).collect();
+            if line.iter().all(|byte| byte.is_ascii_whitespace()) {
+                continue;
+            }
+            events.push(serde_json::from_slice(&line).map_err(DockerError::from));
+        }
+        events
+    }
+}
+
+/// Decode the body of an events response
+pub(super) fn decode(
+    body: impl Stream<Item = Result<impl AsRef<[u8]>, reqwest::Error>>,
+) -> impl Stream<Item = Result<DockerEvent, DockerError>> {
+    let mut decoder = EventDecoder::default();
+    body.flat_map(move |chunk| {
+        let events = match chunk {
+            Ok(bytes) => decoder.push(bytes.as_ref()),
+            Err(e) => vec![Err(DockerError::from(e))],
+        };
+        futures::stream::iter(events)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_events() {
+        let mut decoder = EventDecoder::default();
+        let line = r#"{"Type":"service","Action":"update","Actor":{"ID":"abc","Attributes":{"name":"web","image.new":"nginx:1.27@sha256:1","image.old":"nginx:1.26@sha256:0"}},"scope":"swarm","time":1717236000,"timeNano":1717236000000000000}"#;
+        let (first, second) = line.split_at(40);
+        assert!(decoder.push(first.as_bytes()).is_empty());
+        let events = decoder.push(format!("{}\n", second).as_bytes());
+        let event = events[0].as_ref().unwrap();
+        assert_eq!(event.action, "update");
+        assert_eq!(event.actor.id, "abc");
+        assert_eq!(event.attribute("image.new"), Some("nginx:1.27@sha256:1"));
+    }
+}
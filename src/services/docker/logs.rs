@@ -0,0 +1,202 @@
+//! # Logs of services
+//!
+//! Docker multiplexes stdout and stderr in the same stream. Each frame has a
+//! header of 8 bytes: the stream type (`0` stdin, `1` stdout, `2` stderr),
+//! three zeros and the size of the payload (big endian `u32`).
+//!
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+
+use super::error::DockerError;
+
+const HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
+/// A frame of the logs
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogFrame {
+    pub stream: LogStream,
+    pub message: String,
+}
+
+/// Options of the logs request
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LogsOptions {
+    /// Only logs since this unix timestamp
+    pub since: Option<String>,
+    /// Number of lines from the end of the logs, or `all`
+    pub tail: Option<String>,
+    /// Keep the connection open, streaming new logs
+    #[serde(default)]
+    pub follow: bool,
+    /// Add the timestamp to every line
+    #[serde(default)]
+    pub timestamps: bool,
+}
+
+impl LogsOptions {
+    pub(super) fn query(&self) -> Vec<(&str, String)> {
+        let mut query = vec![
+            ("stdout", "true".to_owned()),
+            ("stderr", "true".to_owned()),
+            ("follow", self.follow.to_string()),
+            ("timestamps", self.timestamps.to_string()),
+        ];
+        if let Some(since) = &self.since {
+            query.push(("since", since.clone()));
+        }
+        if let Some(tail) = &self.tail {
+            query.push(("tail", tail.clone()));
+        }
+        query
+    }
+}
+
+/// Split the multiplexed stream in frames
+#[derive(Debug, Default)]
+pub struct LogDemuxer {
+    buffer: Vec<u8>,
+    /// The stream is not multiplexed (service with tty)
+    raw: bool,
+}
+
+impl LogDemuxer {
+    /// Add the bytes received, returning the complete frames
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<LogFrame> {
+        self.buffer.extend_from_slice(bytes);
+        let mut frames = vec![];
+        if self.raw {
+            frames.extend(self.finish());
+            return frames;
+        }
+        loop {
+            if self.buffer.len() < HEADER_SIZE {
+                break;
+            }
+            let stream = match self.buffer[0] {
+                0 => LogStream::Stdin,
+                1 => LogStream::Stdout,
+                2 => LogStream::Stderr,
+                // not multiplexed (service with tty), forward as it is
+                _ => {
+                    self.raw = true;
+                    frames.extend(self.finish());
+                    break;
+                }
+            };
+            let size = u32::from_be_bytes([
+                self.buffer[4],
+                self.buffer[5],
+                self.buffer[6],
+                self.buffer[7],
+            ]) as usize;
+            if self.buffer.len() < HEADER_SIZE + size {
+                break;
+            }
+            let payload: Vec<u8> = self
+                .buffer
+                .drain(..HEADER_SIZE + size)
+                .skip(HEADER_SIZE)
+                .collect();
+            frames.push(LogFrame {
+                stream,
+                message: String::from_utf8_lossy(&payload).into_owned(),
+            });
+        }
+        frames
+    }
+
+    /// Take the bytes still buffered as a raw frame, when the stream ends
+    /// (like a short output of a service with tty)
+    pub fn finish(&mut self) -> Option<LogFrame> {
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let message = String::from_utf8_lossy(&self.buffer).into_owned();
+        self.buffer.clear();
+        Some(LogFrame {
+            stream: LogStream::Stdout,
+            message,
+        })
+    }
+}
+
+/// Demultiplex the body of a logs response
+pub(super) fn demux(
+    body: impl Stream<Item = Result<impl AsRef<[u8]>, reqwest::Error>>,
+) -> impl Stream<Item = Result<LogFrame, DockerError>> {
+    let mut demuxer = LogDemuxer::default();
+    // `None` marks the end of the body, flushing the buffer
+    body.map(Some)
+        .chain(futures::stream::iter([None]))
+        .flat_map(move |chunk| {
+            let frames = match chunk {
+                Some(Ok(bytes)) => demuxer.push(bytes.as_ref()).into_iter().map(Ok).collect(),
+                Some(Err(e)) => vec![Err(DockerError::from(e))],
+                None => demuxer.finish().into_iter().map(Ok).collect(),
+            };
+            futures::stream::iter(frames)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(stream: u8, message: &str) -> Vec<u8> {
+        let mut bytes = vec![stream, 0, 0, 0];
+        bytes.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(message.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_demux_split_frames() {
+        let mut bytes = frame(1, "hello\n");
+        bytes.extend(frame(2, "error\n"));
+        let mut demuxer = LogDemuxer::default();
+        // the second frame arrives in two chunks
+        let frames = demuxer.push(&bytes[..20]);
+        assert_eq!(
+            frames,
+            vec![LogFrame {
+                stream: LogStream::Stdout,
+                message: "hello\n".into()
+            }]
+        );
+        let frames = demuxer.push(&bytes[20..]);
+        assert_eq!(
+            frames,
+            vec![LogFrame {
+                stream: LogStream::Stderr,
+                message: "error\n".into()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_demux_raw_stream() {
+        let mut demuxer = LogDemuxer::default();
+        let frames = demuxer.push(b"plain tty output\n");
+        assert_eq!(frames[0].message, "plain tty output\n");
+        assert_eq!(frames[0].stream, LogStream::Stdout);
+        // once raw, short chunks are forwarded at once
+        let frames = demuxer.push(b"ok\n");
+        assert_eq!(frames[0].message, "ok\n");
+    }
+
+    #[tokio::test]
+    async fn test_demux_short_raw_end() {
+        let body = futures::stream::iter([Ok::<_, reqwest::Error>(b"ok\n".to_vec())]);
+        let frames = demux(body).collect::<Vec<_>>().await;
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].as_ref().unwrap().message, "ok\n");
+    }
+}
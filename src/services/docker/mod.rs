@@ -1,8 +1,29 @@
 pub mod error;
+pub mod events;
+pub mod logs;
 pub mod types;
 
+use std::{
+    collections::HashSet,
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
 use error::DockerError;
-use types::Service;
+use events::DockerEvent;
+use futures::Stream;
+use logs::{LogFrame, LogsOptions};
+use tracing::{instrument, warn};
+use types::{
+    DockerVersion, KeyValueChanges, Node, RolloutConfig, Service, ServiceCreateResponse,
+    ServiceSpec, ServiceSpecUpdateConfig, ServiceStatus, ServiceUpdateOptions, SystemInfo, Task,
+    TaskResume, UpdateConfigChange,
+};
+
+/// Label with the name of the stack of a service
+pub const STACK_NAMESPACE_LABEL: &str = "com.docker.stack.namespace";
+/// Label of the stack services with their image, kept along the image
+pub const STACK_IMAGE_LABEL: &str = "com.docker.stack.image";
 
 /// Docker Builder
 ///
@@ -16,6 +37,7 @@ use types::Service;
 ///
 pub struct DockerBuilder {
     http_url: String,
+    cache_ttl: Duration,
 }
 
 impl Default for DockerBuilder {
@@ -28,14 +50,22 @@ impl DockerBuilder {
     pub fn builder() -> Self {
         DockerBuilder {
             http_url: "http://localhost:8080".to_owned(),
+            cache_ttl: Duration::from_secs(60),
         }
     }
     pub fn with_http_url(mut self, http_url: &str) -> Self {
         self.http_url = http_url.to_owned();
         self
     }
+    /// Max age of the services cache, see [Docker::cache_enable]
+    pub fn with_cache_ttl(mut self, cache_ttl: Duration) -> Self {
+        self.cache_ttl = cache_ttl;
+        self
+    }
     pub fn build(self) -> Docker {
-        Docker::new(&self.http_url)
+        let mut docker = Docker::new(&self.http_url);
+        docker.cache_ttl = self.cache_ttl;
+        docker
     }
 }
 
@@ -43,9 +73,35 @@ impl DockerBuilder {
 ///
 /// Implement the basic services to interact with the Docker API
 /// - services_list: List all services
+/// - service_inspect: Get a service by id or name
+/// - service_status: Get the running and desired tasks of a service
+/// - service_create: Create a new service
+/// - service_delete: Remove a service
+/// - tasks_list: List the tasks, optionally of a service
+/// - nodes_list: List the nodes of the swarm
+/// - service_logs: Stream the logs of a service
+/// - events: Stream the docker events
+///
+/// The updates of a service are done by the [Service] itself:
+/// - update_image: Update the image of the service
+/// - rollback: Rollback to the previous spec
 ///
 pub struct Docker {
     http_url: String,
+    cache_ttl: Duration,
+    cache: RwLock<ServicesCache>,
+}
+
+/// Services cache, only used while the docker events are followed
+#[derive(Default)]
+struct ServicesCache {
+    enabled: bool,
+    generation: u64,
+    fetched_at: Option<Instant>,
+    services: Vec<Service>,
+    /// Ids of the services created by the updater (canaries, jobs), until
+    /// their removal event
+    created: HashSet<String>,
 }
 
 impl Docker {
@@ -63,6 +119,8 @@ impl Docker {
     pub fn new(http_url: &str) -> Self {
         Docker {
             http_url: http_url.to_owned(),
+            cache_ttl: Duration::from_secs(60),
+            cache: RwLock::new(ServicesCache::default()),
         }
     }
     /// List all services
@@ -76,17 +134,259 @@ impl Docker {
     ///    println!("{:?}", service);
     /// }
     /// ```
+    #[instrument(skip_all)]
     pub async fn services_list(&self) -> Result<Vec<Service>, DockerError> {
-        let url = format!("{}/services", self.http_url);
-        let services = reqwest::get(&url)
+        let generation = {
+            let cache = self.cache.read().unwrap();
+            let fresh = cache
+                .fetched_at
+                .is_some_and(|fetched_at| fetched_at.elapsed() < self.cache_ttl);
+            if cache.enabled && fresh {
+                return Ok(cache.services.clone());
+            }
+            cache.generation
+        };
+        let url = format!("{}/services?status=true", self.http_url);
+        let services: Vec<Service> = reqwest::get(&url)
             .await?
             .json::<Vec<Service>>()
             .await?
             .into_iter()
             .map(|service| service.with_service_http(&self.http_url))
             .collect();
+        let mut cache = self.cache.write().unwrap();
+        // an event may have invalidated the cache while fetching
+        if cache.enabled && cache.generation == generation {
+            cache.fetched_at = Some(Instant::now());
+            cache.services = services.clone();
+        }
         Ok(services)
     }
+    /// Enable (or disable) the services cache used by [services_list](Docker::services_list)
+    ///
+    /// The cache must only be enabled while the docker events are followed,
+    /// invalidating it with [cache_invalidate](Docker::cache_invalidate).
+    pub fn cache_enable(&self, enabled: bool) {
+        let mut cache = self.cache.write().unwrap();
+        cache.enabled = enabled;
+        cache.generation += 1;
+        cache.fetched_at = None;
+        if !enabled {
+            cache.created.clear();
+        }
+    }
+    /// Invalidate the services cache
+    pub fn cache_invalidate(&self) {
+        let mut cache = self.cache.write().unwrap();
+        cache.generation += 1;
+        cache.fetched_at = None;
+    }
+    /// Get a service from the cache, even when it is not fresh
+    pub fn cached_service(&self, id: &str) -> Option<Service> {
+        let cache = self.cache.read().unwrap();
+        cache
+            .services
+            .iter()
+            .find(|service| service.id == id)
+            .cloned()
+    }
+    /// Check if the service was created by the updater, see
+    /// [service_create](Docker::service_create)
+    ///
+    /// With `forget`, on its removal, the service is not checked again.
+    pub fn created_by_us(&self, id: &str, forget: bool) -> bool {
+        let mut cache = self.cache.write().unwrap();
+        if forget {
+            cache.created.remove(id)
+        } else {
+            cache.created.contains(id)
+        }
+    }
+    /// Get a service by id or name
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let docker = Docker::new("http://localhost:8080".to_owned());
+    /// let service = docker.service_inspect("my-service").await.unwrap();
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn service_inspect(&self, id: &str) -> Result<Service, DockerError> {
+        let url = format!("{}/services/{}", self.http_url, id);
+        let response = reqwest::get(&url).await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(DockerError::ServiceNotFound(id.to_owned()));
+        }
+        let service = response.error_for_status()?.json::<Service>().await?;
+        Ok(service.with_service_http(&self.http_url))
+    }
+    /// Check the daemon answers
+    #[instrument(skip_all)]
+    pub async fn ping(&self) -> Result<(), DockerError> {
+        let url = format!("{}/_ping", self.http_url);
+        reqwest::get(&url).await?.error_for_status()?;
+        Ok(())
+    }
+    /// Get the system info of the daemon, with its swarm state
+    #[instrument(skip_all)]
+    pub async fn info(&self) -> Result<SystemInfo, DockerError> {
+        let url = format!("{}/info", self.http_url);
+        Ok(reqwest::get(&url)
+            .await?
+            .error_for_status()?
+            .json::<SystemInfo>()
+            .await?)
+    }
+    /// Get the version of the daemon and of its api
+    #[instrument(skip_all)]
+    pub async fn version(&self) -> Result<DockerVersion, DockerError> {
+        let url = format!("{}/version", self.http_url);
+        Ok(reqwest::get(&url)
+            .await?
+            .error_for_status()?
+            .json::<DockerVersion>()
+            .await?)
+    }
+    /// Get the running and desired tasks of a service
+    #[instrument(skip(self))]
+    pub async fn service_status(&self, id: &str) -> Result<ServiceStatus, DockerError> {
+        let url = format!("{}/services", self.http_url);
+        let filters = serde_json::json!({ "id": [id] }).to_string();
+        let services = reqwest::Client::new()
+            .get(&url)
+            .query(&[("status", "true"), ("filters", filters.as_str())])
+            .send()
+            .await?
+            .json::<Vec<Service>>()
+            .await?;
+        services
+            .into_iter()
+            .find(|service| service.id == id)
+            .and_then(|service| service.service_status)
+            .ok_or_else(|| DockerError::ServiceNotFound(id.to_owned()))
+    }
+    /// Create a new service with the registry credentials (`X-Registry-Auth`)
+    /// the nodes use to pull its image, returning its id
+    ///
+    /// The services created are managed by the updater: their removal is not
+    /// an external change, see [created_by_us](Docker::created_by_us).
+    #[instrument(skip_all, fields(service = %spec.name))]
+    pub async fn service_create_with_auth(
+        &self,
+        spec: &ServiceSpec,
+        registry_auth: Option<&str>,
+    ) -> Result<String, DockerError> {
+        let url = format!("{}/services/create", self.http_url);
+        let mut request = reqwest::Client::new().post(&url).json(spec);
+        if let Some(registry_auth) = registry_auth {
+            request = request.header("X-Registry-Auth", registry_auth);
+        }
+        let response = request.send().await?;
+        if response.status().is_success() {
+            let id = response.json::<ServiceCreateResponse>().await?.id;
+            // remembered for the events, only while they are followed
+            let mut cache = self.cache.write().unwrap();
+            if cache.enabled {
+                cache.created.insert(id.clone());
+            }
+            Ok(id)
+        } else {
+            Err(DockerError::ServiceCreateError(response.text().await?))
+        }
+    }
+    /// List the tasks, optionally only the tasks of a service (id or name)
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let docker = Docker::new("http://localhost:8080".to_owned());
+    /// let tasks = docker.tasks_list(Some("my-service")).await.unwrap();
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn tasks_list(&self, service: Option<&str>) -> Result<Vec<Task>, DockerError> {
+        let url = format!("{}/tasks", self.http_url);
+        let mut request = reqwest::Client::new().get(&url);
+        if let Some(service) = service {
+            let filters = serde_json::json!({ "service": [service] }).to_string();
+            request = request.query(&[("filters", filters)]);
+        }
+        Ok(request.send().await?.json::<Vec<Task>>().await?)
+    }
+    /// List the nodes of the swarm
+    #[instrument(skip_all)]
+    pub async fn nodes_list(&self) -> Result<Vec<Node>, DockerError> {
+        let url = format!("{}/nodes", self.http_url);
+        Ok(reqwest::get(&url).await?.json::<Vec<Node>>().await?)
+    }
+    /// Stream the logs of a service, stdout and stderr demultiplexed
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let docker = Docker::new("http://localhost:8080".to_owned());
+    /// let logs = docker.service_logs("my-service", &LogsOptions::default()).await.unwrap();
+    /// ```
+    #[instrument(skip(self, options))]
+    pub async fn service_logs(
+        &self,
+        id: &str,
+        options: &LogsOptions,
+    ) -> Result<impl Stream<Item = Result<LogFrame, DockerError>>, DockerError> {
+        let url = format!("{}/services/{}/logs", self.http_url, id);
+        let response = reqwest::Client::new()
+            .get(&url)
+            .query(&options.query())
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(DockerError::ServiceNotFound(id.to_owned()));
+        }
+        let response = response.error_for_status()?;
+        Ok(logs::demux(response.bytes_stream()))
+    }
+    /// Stream the docker events of a type (`service`, `node`, ...)
+    #[instrument(skip(self))]
+    pub async fn events(
+        &self,
+        r#type: &str,
+    ) -> Result<impl Stream<Item = Result<DockerEvent, DockerError>>, DockerError> {
+        let url = format!("{}/events", self.http_url);
+        let filters = serde_json::json!({ "type": [r#type] }).to_string();
+        let response = reqwest::Client::new()
+            .get(&url)
+            .query(&[("filters", filters)])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(events::decode(response.bytes_stream()))
+    }
+    /// Create a service removed by the returned guard, even when the future
+    /// using it is dropped (like the jobs, the canaries and the pre-pulls)
+    #[instrument(skip_all, fields(service = %spec.name))]
+    pub async fn service_create_temporary(
+        &self,
+        spec: &ServiceSpec,
+        registry_auth: Option<&str>,
+    ) -> Result<TemporaryService, DockerError> {
+        let id = self.service_create_with_auth(spec, registry_auth).await?;
+        Ok(TemporaryService {
+            http_url: self.http_url.clone(),
+            id,
+            name: spec.name.clone(),
+            removed: false,
+        })
+    }
+    /// Remove a service
+    #[instrument(skip(self))]
+    pub async fn service_delete(&self, id: &str) -> Result<(), DockerError> {
+        let url = format!("{}/services/{}", self.http_url, id);
+        let response = reqwest::Client::new().delete(&url).send().await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(DockerError::ServiceDeleteError(response.text().await?))
+        }
+    }
     // pub async fn services_list(&self) -> Result<Vec<Service>> {
     //     let url = format!("{}/services", self.http_url);
     //     let response = reqwest::get(&url).await?.text().await?;
@@ -95,19 +395,67 @@ impl Docker {
     // }
 }
 
+/// A service created for a while, removed with [TemporaryService::remove] or
+/// in background when dropped
+pub struct TemporaryService {
+    http_url: String,
+    pub id: String,
+    pub name: String,
+    removed: bool,
+}
+
+impl TemporaryService {
+    /// Remove the service, logging the errors
+    pub async fn remove(mut self) {
+        self.removed = true;
+        if let Err(e) = Docker::new(&self.http_url).service_delete(&self.id).await {
+            warn!("Error removing service {}: {}", self.name, e);
+        }
+    }
+}
+
+impl Drop for TemporaryService {
+    fn drop(&mut self) {
+        if self.removed {
+            return;
+        }
+        let (http_url, id, name) = (self.http_url.clone(), self.id.clone(), self.name.clone());
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    warn!("Removing service {}, its task was cancelled", name);
+                    if let Err(e) = Docker::new(&http_url).service_delete(&id).await {
+                        warn!("Error removing service {}: {}", name, e);
+                    }
+                });
+            }
+            Err(_) => warn!("Service {} not removed, no runtime", name),
+        }
+    }
+}
+
 impl Service {
     pub async fn update_image(&mut self, image: &str, tag: &str) -> Result<String, DockerError> {
+        self.set_image(&format!("{}:{}", image, tag));
+        self.update().await
+    }
+    /// Update the image pinned to a digest (`image:tag@digest`)
+    pub async fn update_image_digest(
+        &mut self,
+        image: &str,
+        tag: &str,
+        digest: &str,
+    ) -> Result<String, DockerError> {
+        self.set_image(&format!("{}:{}@{}", image, tag, digest));
+        self.update().await
+    }
+    /// Send the current spec to docker
+    #[instrument(skip_all, fields(service = %self.spec.name))]
+    pub async fn update(&self) -> Result<String, DockerError> {
         let url = format!(
             "{}/update?version={}",
             self.service_http_url, self.version.index
         );
-        self.spec.task_template.container_spec.image = format!("{}:{}", image, tag);
-        if let Some(labels) = &mut self.spec.labels {
-            labels.insert(
-                "com.docker.stack.image".into(),
-                self.spec.task_template.container_spec.image.clone(),
-            );
-        }
         let client = reqwest::Client::new();
         let response = client.post(&url).json(&self.spec).send().await?;
         if response.status().is_success() {
@@ -116,17 +464,301 @@ impl Service {
             Err(DockerError::ServiceUpdateError(response.text().await?))
         }
     }
+    /// Rollback the service to its previous spec
+    #[instrument(skip_all, fields(service = %self.spec.name))]
+    pub async fn rollback(&self) -> Result<String, DockerError> {
+        let url = format!(
+            "{}/update?version={}&rollback=previous",
+            self.service_http_url, self.version.index
+        );
+        let client = reqwest::Client::new();
+        let response = client.post(&url).json(&self.spec).send().await?;
+        if response.status().is_success() {
+            Ok("Service rolled back".to_owned())
+        } else {
+            Err(DockerError::ServiceUpdateError(response.text().await?))
+        }
+    }
+    fn set_image(&mut self, image: &str) {
+        self.spec.task_template.container_spec.image = image.to_owned();
+        if let Some(labels) = &mut self.spec.labels {
+            labels.insert(STACK_IMAGE_LABEL.into(), image.to_owned());
+        }
+    }
+    /// Apply the changes of the options to the service spec
+    ///
+    /// The spec is only changed locally, it is sent to docker by
+    /// [update_image](Service::update_image).
+    pub fn apply_options(&mut self, options: &ServiceUpdateOptions) -> Result<(), DockerError> {
+        // validate everything before changing the spec
+        if let Some(env) = &options.env {
+            let keys = self
+                .spec
+                .task_template
+                .container_spec
+                .env
+                .iter()
+                .flatten()
+                .map(|entry| entry.split_once('=').map_or(entry.as_str(), |(key, _)| key))
+                .collect::<Vec<&str>>();
+            env.validate("env", &keys)?;
+        }
+        if let Some(labels) = &options.labels {
+            let keys = self
+                .spec
+                .labels
+                .iter()
+                .flat_map(|labels| labels.keys())
+                .map(|key| key.as_str())
+                .collect::<Vec<&str>>();
+            labels.validate("labels", &keys)?;
+        }
+        let update_config = options
+            .update_config
+            .as_ref()
+            .map(|change| {
+                change.applied(
+                    self.spec.update_config.clone().unwrap_or_default(),
+                    &["continue", "pause", "rollback"],
+                )
+            })
+            .transpose()?;
+        let rollback_config = options
+            .rollback_config
+            .as_ref()
+            .map(|change| {
+                change.applied(
+                    self.spec.rollback_config.clone().unwrap_or_default(),
+                    &["continue", "pause"],
+                )
+            })
+            .transpose()?;
+
+        if let Some(env) = &options.env {
+            let entries = self
+                .spec
+                .task_template
+                .container_spec
+                .env
+                .get_or_insert_with(Default::default);
+            entries.retain(|entry| {
+                let key = entry.split_once('=').map_or(entry.as_str(), |(key, _)| key);
+                !env.unset.iter().any(|k| k == key) && !env.set.contains_key(key)
+            });
+            let mut set = env.set.iter().collect::<Vec<_>>();
+            set.sort();
+            entries.extend(
+                set.into_iter()
+                    .map(|(key, value)| format!("{}={}", key, value)),
+            );
+        }
+        if let Some(changes) = &options.labels {
+            let labels = self.spec.labels.get_or_insert_with(Default::default);
+            for key in &changes.unset {
+                labels.remove(key);
+            }
+            labels.extend(changes.set.clone());
+        }
+        if update_config.is_some() {
+            self.spec.update_config = update_config;
+        }
+        if rollback_config.is_some() {
+            self.spec.rollback_config = rollback_config;
+        }
+        Ok(())
+    }
+    /// The update and rollback configs, to put back after an update changing them
+    pub fn rollout_config(&self) -> RolloutConfig {
+        RolloutConfig {
+            update_config: self.spec.update_config.clone(),
+            rollback_config: self.spec.rollback_config.clone(),
+            previous_rollout: self
+                .update_status
+                .as_ref()
+                .and_then(|status| status.started_at),
+        }
+    }
+    /// Get the value of a service label
+    pub fn label(&self, key: &str) -> Option<&str> {
+        self.spec
+            .labels
+            .as_ref()
+            .and_then(|labels| labels.get(key))
+            .map(|value| value.as_str())
+    }
     pub fn with_service_http(mut self, http_url: &str) -> Self {
         self.service_http_url = format!("{}/services/{}", http_url, self.id);
         self
     }
 }
 
+impl KeyValueChanges {
+    fn validate(&self, field: &str, existing: &[&str]) -> Result<(), DockerError> {
+        if let Some(key) = self
+            .set
+            .keys()
+            .find(|key| key.is_empty() || key.contains('='))
+        {
+            return Err(DockerError::InvalidChange(format!(
+                "{}: invalid key {:?}",
+                field, key
+            )));
+        }
+        if let Some(key) = self
+            .unset
+            .iter()
+            .find(|key| !existing.contains(&key.as_str()))
+        {
+            return Err(DockerError::InvalidChange(format!(
+                "{}: can't unset {}, it doesn't exist",
+                field, key
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Docker expects the durations in nanoseconds
+const NANOSECONDS: u64 = 1_000_000_000;
+
+/// Convert seconds of the request to nanoseconds, failing when it overflows
+fn nanoseconds(field: &str, seconds: u64) -> Result<u64, DockerError> {
+    seconds.checked_mul(NANOSECONDS).ok_or_else(|| {
+        DockerError::InvalidChange(format!("{} of {} seconds is too large", field, seconds))
+    })
+}
+
+impl UpdateConfigChange {
+    /// The config with the change, converting the seconds of the request to
+    /// nanoseconds, or the error of an invalid change
+    fn applied(
+        &self,
+        mut config: ServiceSpecUpdateConfig,
+        failure_actions: &[&str],
+    ) -> Result<ServiceSpecUpdateConfig, DockerError> {
+        if let Some(action) = &self.failure_action {
+            if !failure_actions.contains(&action.as_str()) {
+                return Err(DockerError::InvalidChange(format!(
+                    "failure_action must be one of {:?}",
+                    failure_actions
+                )));
+            }
+            config.failure_action = Some(action.clone());
+        }
+        if let Some(order) = &self.order {
+            if order != "stop-first" && order != "start-first" {
+                return Err(DockerError::InvalidChange(
+                    "order must be stop-first or start-first".into(),
+                ));
+            }
+            config.order = Some(order.clone());
+        }
+        if let Some(ratio) = self.max_failure_ratio {
+            if !(0.0..=1.0).contains(&ratio) {
+                return Err(DockerError::InvalidChange(
+                    "max_failure_ratio must be between 0 and 1".into(),
+                ));
+            }
+            config.max_failure_ratio = Some(ratio);
+        }
+        if let Some(parallelism) = self.parallelism {
+            config.parallelism = Some(parallelism);
+        }
+        if let Some(delay) = self.delay {
+            config.delay = Some(nanoseconds("delay", delay)?);
+        }
+        if let Some(monitor) = self.monitor {
+            config.monitor = Some(nanoseconds("monitor", monitor)?);
+        }
+        Ok(config)
+    }
+}
+
+impl TaskResume {
+    /// Resume of the task, with the hostname of its node when it is known
+    pub fn new(task: Task, nodes: &[Node]) -> Self {
+        let node = task.node_id.as_ref().and_then(|id| {
+            nodes
+                .iter()
+                .find(|node| node.id == *id)
+                .map(|node| node.description.hostname.clone())
+        });
+        let image = task.spec.container_spec.image;
+        let digest = image.split_once('@').map(|(_, digest)| digest.to_owned());
+        Self {
+            id: task.id,
+            slot: task.slot,
+            node_id: task.node_id,
+            node,
+            state: task.status.state,
+            desired_state: task.desired_state,
+            message: task.status.message,
+            error: task.status.err,
+            exit_code: task
+                .status
+                .container_status
+                .and_then(|status| status.exit_code),
+            image,
+            digest,
+            updated_at: task.updated_at,
+        }
+    }
+}
+
+impl From<types::Service> for types::ServiceDetails {
+    fn from(value: types::Service) -> Self {
+        let digest = value
+            .spec
+            .task_template
+            .container_spec
+            .image
+            .split_once('@')
+            .map(|(_, digest)| digest.to_owned());
+        let stack = value
+            .label(STACK_NAMESPACE_LABEL)
+            .map(|stack| stack.to_owned());
+        let mode = value.spec.mode.as_ref();
+        let replicas = mode
+            .and_then(|mode| mode.replicated.as_ref())
+            .map(|replicated| replicated.replicas);
+        let mode = match mode {
+            Some(mode) if mode.replicated.is_some() => "replicated",
+            Some(mode) if mode.global.is_some() => "global",
+            _ => "unknown",
+        };
+        let status = value.service_status.as_ref();
+        Self {
+            digest,
+            stack,
+            mode: mode.to_owned(),
+            replicas,
+            running_tasks: status.map(|status| status.running_tasks),
+            desired_tasks: status.map(|status| status.desired_tasks),
+            update_status: value.update_status.clone(),
+            resume: value.into(),
+        }
+    }
+}
+
+/// Split an image reference in its image and tag, without the digest
+///
+/// The tag is after the last `/` and defaults to `latest`, like docker does:
+/// `nginx` and `nginx@sha256:…` are `nginx` `latest`, `registry:5000/app:1.0`
+/// is `registry:5000/app` `1.0`.
+pub fn split_image(reference: &str) -> (&str, &str) {
+    let name = reference
+        .split_once('@')
+        .map_or(reference, |(name, _)| name);
+    match name.rsplit_once(':') {
+        Some((image, tag)) if !tag.contains('/') => (image, tag),
+        _ => (name, "latest"),
+    }
+}
+
 impl From<types::Service> for types::ServiceResume {
     fn from(value: types::Service) -> Self {
-        let mut parts = value.spec.task_template.container_spec.image.split(':');
-        let image = parts.next().unwrap().to_string();
-        let tag = parts.next().unwrap().split('@').next().unwrap().to_string();
+        let (image, tag) = split_image(&value.spec.task_template.container_spec.image);
+        let (image, tag) = (image.to_owned(), tag.to_owned());
         Self {
             id: value.id.clone(),
             version: value.version.index,
@@ -142,11 +774,286 @@ impl From<types::Service> for types::ServiceResume {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[tokio::test]
     async fn test_get_service_list() {
         let docker = DockerBuilder::builder().build();
         let services = docker.services_list().await.unwrap();
-        assert!(services.len() > 0);
+        assert!(!services.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_service_inspect_errors() {
+        use axum::{extract::Path, http::StatusCode, routing::get, Router};
+        let app = Router::new().route(
+            "/services/:id",
+            get(|Path(id): Path<String>| async move {
+                match id.as_str() {
+                    "missing" => StatusCode::NOT_FOUND,
+                    _ => StatusCode::INTERNAL_SERVER_ERROR,
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let docker = DockerBuilder::builder().with_http_url(&url).build();
+        assert!(matches!(
+            docker.service_inspect("missing").await,
+            Err(DockerError::ServiceNotFound(_))
+        ));
+        assert!(matches!(
+            docker.service_inspect("web").await,
+            Err(DockerError::DockerAPIError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_temporary_service_removed_on_drop() {
+        use axum::{
+            extract::{Path, State},
+            routing::{delete, post},
+            Json, Router,
+        };
+        use std::sync::{Arc, Mutex};
+        let deleted = Arc::new(Mutex::new(vec![]));
+        let app = Router::new()
+            .route(
+                "/services/create",
+                post(|| async { Json(serde_json::json!({ "ID": "job1" })) }),
+            )
+            .route(
+                "/services/:id",
+                delete(
+                    |State(deleted): State<Arc<Mutex<Vec<String>>>>, Path(id): Path<String>| async move {
+                        deleted.lock().unwrap().push(id);
+                    },
+                ),
+            )
+            .with_state(deleted.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let docker = DockerBuilder::builder().with_http_url(&url).build();
+        let spec: ServiceSpec = serde_json::from_value(serde_json::json!({
+            "Name": "web-job",
+            "TaskTemplate": { "ContainerSpec": { "Image": "nginx" } }
+        }))
+        .unwrap();
+
+        // a cancelled future drops the guard
+        let service = docker.service_create_temporary(&spec, None).await.unwrap();
+        drop(service);
+        for _ in 0..50 {
+            if !deleted.lock().unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(*deleted.lock().unwrap(), vec!["job1"]);
+
+        let service = docker.service_create_temporary(&spec, None).await.unwrap();
+        service.remove().await;
+        assert_eq!(deleted.lock().unwrap().len(), 2);
+    }
+
+    fn service() -> Service {
+        serde_json::from_value(serde_json::json!({
+            "ID": "abc",
+            "Version": { "Index": 10 },
+            "CreatedAt": "2024-06-01T10:00:00Z",
+            "UpdatedAt": "2024-06-01T10:00:00Z",
+            "Spec": {
+                "Name": "web",
+                "Labels": { "team": "web", "tier": "front" },
+                "TaskTemplate": {
+                    "ContainerSpec": {
+                        "Image": "nginx:1.26",
+                        "Env": ["LOG_LEVEL=info", "FEATURE_X=0", "DEBUG"]
+                    }
+                },
+                "UpdateConfig": { "Parallelism": 1, "Order": "stop-first" }
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_split_image() {
+        assert_eq!(split_image("nginx:1.26"), ("nginx", "1.26"));
+        assert_eq!(split_image("nginx"), ("nginx", "latest"));
+        assert_eq!(split_image("nginx@sha256:abcd"), ("nginx", "latest"));
+        assert_eq!(split_image("nginx:1.26@sha256:abcd"), ("nginx", "1.26"));
+        assert_eq!(
+            split_image("registry:5000/app:1.0"),
+            ("registry:5000/app", "1.0")
+        );
+        assert_eq!(
+            split_image("registry:5000/app@sha256:abcd"),
+            ("registry:5000/app", "latest")
+        );
+    }
+
+    #[test]
+    fn test_service_resume_untagged() {
+        for (reference, image, tag) in [
+            ("nginx", "nginx", "latest"),
+            ("nginx@sha256:abcd", "nginx", "latest"),
+            ("registry:5000/app:1.0", "registry:5000/app", "1.0"),
+        ] {
+            let mut service = service();
+            service.spec.task_template.container_spec.image = reference.into();
+            let resume = types::ServiceResume::from(service.clone());
+            assert_eq!((resume.image.as_str(), resume.tag.as_str()), (image, tag));
+            let details = types::ServiceDetails::from(service);
+            assert_eq!(details.resume.image, image);
+        }
+    }
+
+    #[test]
+    fn test_apply_update_config() {
+        let mut service = service();
+        let options = ServiceUpdateOptions {
+            update_config: Some(UpdateConfigChange {
+                delay: Some(5),
+                order: Some("start-first".into()),
+                ..Default::default()
+            }),
+            rollback_config: Some(UpdateConfigChange {
+                parallelism: Some(0),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        service.apply_options(&options).unwrap();
+        let update_config = service.spec.update_config.unwrap();
+        assert_eq!(update_config.parallelism, Some(1));
+        assert_eq!(update_config.delay, Some(5_000_000_000));
+        assert_eq!(update_config.order.as_deref(), Some("start-first"));
+        assert_eq!(service.spec.rollback_config.unwrap().parallelism, Some(0));
+    }
+
+    #[test]
+    fn test_apply_env_and_labels() {
+        let mut service = service();
+        let options = ServiceUpdateOptions {
+            env: Some(KeyValueChanges {
+                set: HashMap::from([
+                    ("FEATURE_X".into(), "1".into()),
+                    ("NEW".into(), "a=b".into()),
+                ]),
+                unset: vec!["DEBUG".into()],
+            }),
+            labels: Some(KeyValueChanges {
+                set: HashMap::from([("release".into(), "42".into())]),
+                unset: vec!["tier".into()],
+            }),
+            ..Default::default()
+        };
+        service.apply_options(&options).unwrap();
+        assert_eq!(
+            service.spec.task_template.container_spec.env.unwrap(),
+            vec!["LOG_LEVEL=info", "FEATURE_X=1", "NEW=a=b"]
+        );
+        let labels = service.spec.labels.unwrap();
+        assert_eq!(labels.get("release").unwrap(), "42");
+        assert_eq!(labels.get("team").unwrap(), "web");
+        assert!(!labels.contains_key("tier"));
+    }
+
+    #[test]
+    fn test_apply_unset_missing_key() {
+        let mut service = service();
+        let options = ServiceUpdateOptions {
+            env: Some(KeyValueChanges {
+                set: HashMap::from([("FEATURE_X".into(), "1".into())]),
+                unset: vec!["MISSING".into()],
+            }),
+            ..Default::default()
+        };
+        assert!(service.apply_options(&options).is_err());
+        // nothing is changed when the validation fails
+        assert_eq!(
+            service.spec.task_template.container_spec.env.unwrap(),
+            vec!["LOG_LEVEL=info", "FEATURE_X=0", "DEBUG"]
+        );
+    }
+
+    #[test]
+    fn test_task_resume() {
+        let task: Task = serde_json::from_value(serde_json::json!({
+            "ID": "task1",
+            "Version": { "Index": 20 },
+            "CreatedAt": "2024-06-01T10:00:00Z",
+            "UpdatedAt": "2024-06-01T10:01:00Z",
+            "Spec": { "ContainerSpec": { "Image": "nginx:1.26@sha256:abcd" } },
+            "ServiceID": "abc",
+            "Slot": 1,
+            "NodeID": "node1",
+            "Status": {
+                "Timestamp": "2024-06-01T10:01:00Z",
+                "State": "failed",
+                "Message": "started",
+                "Err": "task: non-zero exit (1)",
+                "ContainerStatus": { "ContainerID": "c1", "ExitCode": 1 }
+            },
+            "DesiredState": "shutdown"
+        }))
+        .unwrap();
+        let node: Node = serde_json::from_value(serde_json::json!({
+            "ID": "node1",
+            "Version": { "Index": 5 },
+            "CreatedAt": "2024-06-01T09:00:00Z",
+            "UpdatedAt": "2024-06-01T09:00:00Z",
+            "Spec": { "Role": "worker", "Availability": "active" },
+            "Description": { "Hostname": "worker-1" },
+            "Status": { "State": "ready", "Addr": "10.0.0.2" }
+        }))
+        .unwrap();
+        let resume = TaskResume::new(task, &[node]);
+        assert_eq!(resume.node.as_deref(), Some("worker-1"));
+        assert_eq!(resume.digest.as_deref(), Some("sha256:abcd"));
+        assert_eq!(resume.exit_code, Some(1));
+        assert_eq!(resume.state, "failed");
+        assert_eq!(resume.desired_state, "shutdown");
+    }
+
+    #[test]
+    fn test_apply_invalid_update_config() {
+        let mut service = service();
+        let options = ServiceUpdateOptions {
+            rollback_config: Some(UpdateConfigChange {
+                failure_action: Some("rollback".into()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(service.apply_options(&options).is_err());
+    }
+
+    #[test]
+    fn test_apply_update_config_overflow() {
+        let mut service = service();
+        let options = ServiceUpdateOptions {
+            env: Some(KeyValueChanges {
+                set: HashMap::from([("FEATURE_X".into(), "1".into())]),
+                unset: vec![],
+            }),
+            update_config: Some(UpdateConfigChange {
+                monitor: Some(u64::MAX / 10),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            service.apply_options(&options),
+            Err(DockerError::InvalidChange(_))
+        ));
+        // nothing is changed when the validation fails
+        assert_eq!(
+            service.spec.task_template.container_spec.env.unwrap(),
+            vec!["LOG_LEVEL=info", "FEATURE_X=0", "DEBUG"]
+        );
     }
 }
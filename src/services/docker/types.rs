@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceContainerPrivileges {
     #[serde(rename = "CredentialSpec")]
     pub credential_spec: Option<HashMap<String, String>>,
@@ -11,7 +11,7 @@ pub struct ServiceContainerPrivileges {
     pub selinux_context: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceContainerMountVolumeOptionsDriverConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Name")]
@@ -20,7 +20,7 @@ pub struct ServiceContainerMountVolumeOptionsDriverConfig {
     pub options: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceContainerMountVolumeOptions {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Labels")]
@@ -30,7 +30,7 @@ pub struct ServiceContainerMountVolumeOptions {
     pub driver_config: Option<ServiceContainerMountVolumeOptionsDriverConfig>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceContainerMount {
     #[serde(rename = "Type")]
     pub r#type: String,
@@ -43,7 +43,7 @@ pub struct ServiceContainerMount {
     pub volume_options: Option<ServiceContainerMountVolumeOptions>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceContainerSpecConfigFile {
     #[serde(rename = "Name")]
     pub name: String,
@@ -55,7 +55,7 @@ pub struct ServiceContainerSpecConfigFile {
     pub mode: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceContainerSpecConfig {
     #[serde(rename = "File")]
     pub file: Option<ServiceContainerSpecConfigFile>,
@@ -65,7 +65,17 @@ pub struct ServiceContainerSpecConfig {
     pub config_name: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceContainerSpecSecret {
+    #[serde(rename = "File")]
+    pub file: Option<ServiceContainerSpecConfigFile>,
+    #[serde(rename = "SecretID")]
+    pub secret_id: String,
+    #[serde(rename = "SecretName")]
+    pub secret_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceContainerSpecHealthCheck {
     #[serde(rename = "Test")]
     pub test: Vec<String>,
@@ -80,7 +90,7 @@ pub struct ServiceContainerSpecHealthCheck {
     pub retries: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceContainerSpec {
     #[serde(rename = "Image")]
     pub image: String,
@@ -88,6 +98,9 @@ pub struct ServiceContainerSpec {
     #[serde(rename = "Labels")]
     pub labels: Option<HashMap<String, String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Command")]
+    pub command: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Args")]
     pub args: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -103,6 +116,9 @@ pub struct ServiceContainerSpec {
     #[serde(rename = "Configs")]
     pub configs: Option<Vec<ServiceContainerSpecConfig>>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Secrets")]
+    pub secrets: Option<Vec<ServiceContainerSpecSecret>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Healthcheck")]
     pub health_check: Option<ServiceContainerSpecHealthCheck>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -110,7 +126,7 @@ pub struct ServiceContainerSpec {
     pub isolation: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceTaskTemplatePlacement {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Constraints")]
@@ -120,7 +136,7 @@ pub struct ServiceTaskTemplatePlacement {
     pub platforms: Option<Vec<HashMap<String, String>>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceTaskTemplateNetworks {
     #[serde(rename = "Target")]
     pub target: String,
@@ -129,7 +145,7 @@ pub struct ServiceTaskTemplateNetworks {
     pub aliases: Option<Vec<String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceTaskTemplateResources {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Limits")]
@@ -139,7 +155,23 @@ pub struct ServiceTaskTemplateResources {
     pub reservations: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceTaskTemplateRestartPolicy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Condition")]
+    pub condition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Delay")]
+    pub delay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "MaxAttempts")]
+    pub max_attempts: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Window")]
+    pub window: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceTaskTemplate {
     #[serde(rename = "ContainerSpec")]
     pub container_spec: ServiceContainerSpec,
@@ -153,18 +185,34 @@ pub struct ServiceTaskTemplate {
     pub force_update: Option<u64>,
     #[serde(rename = "Runtime")]
     pub runtime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "RestartPolicy")]
+    pub restart_policy: Option<ServiceTaskTemplateRestartPolicy>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceSpecModeReplicated {
     #[serde(rename = "Replicas")]
     pub replicas: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceSpecModeGlobal {}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceSpecModeReplicatedJob {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "MaxConcurrent")]
+    pub max_concurrent: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "TotalCompletions")]
+    pub total_completions: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceSpecModeGlobalJob {}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ServiceSpecMode {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Replicated")]
@@ -172,9 +220,15 @@ pub struct ServiceSpecMode {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Global")]
     pub global: Option<ServiceSpecModeGlobal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ReplicatedJob")]
+    pub replicated_job: Option<ServiceSpecModeReplicatedJob>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "GlobalJob")]
+    pub global_job: Option<ServiceSpecModeGlobalJob>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceEndpointSpecPortConfig {
     #[serde(rename = "Protocol")]
     pub protocol: String,
@@ -186,7 +240,7 @@ pub struct ServiceEndpointSpecPortConfig {
     pub publish_mode: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceEndpointSpec {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Mode")]
@@ -196,7 +250,32 @@ pub struct ServiceEndpointSpec {
     pub ports: Option<Vec<ServiceEndpointSpecPortConfig>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ServiceSpecUpdateConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Parallelism")]
+    pub parallelism: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Delay")]
+    pub delay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "FailureAction")]
+    pub failure_action: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Monitor")]
+    pub monitor: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "MaxFailureRatio")]
+    pub max_failure_ratio: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Order")]
+    pub order: Option<String>,
+}
+
+/// The rollback config has the same fields of the update config
+pub type ServiceSpecRollbackConfig = ServiceSpecUpdateConfig;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceSpec {
     #[serde(rename = "Name")]
     pub name: String,
@@ -206,17 +285,23 @@ pub struct ServiceSpec {
     pub task_template: ServiceTaskTemplate,
     #[serde(rename = "Mode")]
     pub mode: Option<ServiceSpecMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "UpdateConfig")]
+    pub update_config: Option<ServiceSpecUpdateConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "RollbackConfig")]
+    pub rollback_config: Option<ServiceSpecRollbackConfig>,
     #[serde(rename = "EndpointSpec")]
     pub endpoint_spec: Option<ServiceEndpointSpec>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceVersion {
     #[serde(rename = "Index")]
     pub index: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceEndpointVirtualIP {
     #[serde(rename = "NetworkID")]
     pub network_id: String,
@@ -224,7 +309,7 @@ pub struct ServiceEndpointVirtualIP {
     pub addr: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceEndpoint {
     #[serde(rename = "Spec")]
     pub spec: ServiceEndpointSpec,
@@ -236,19 +321,73 @@ pub struct ServiceEndpoint {
     pub ports: Option<Vec<ServiceEndpointSpecPortConfig>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceUpdateStatus {
     #[serde(rename = "State")]
     pub state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "StartedAt")]
-    pub started_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "CompletedAt")]
-    pub completed_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "Message")]
-    pub message: String,
+    pub message: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    #[serde(rename = "RunningTasks")]
+    pub running_tasks: u64,
+    #[serde(rename = "DesiredTasks")]
+    pub desired_tasks: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "CompletedTasks")]
+    pub completed_tasks: Option<u64>,
+}
+
+/// Swarm part of the system info of the daemon
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SwarmInfo {
+    #[serde(default, rename = "NodeID")]
+    pub node_id: String,
+    /// `active` when the node is part of a swarm
+    #[serde(default, rename = "LocalNodeState")]
+    pub local_node_state: String,
+    /// The node is a manager
+    #[serde(default, rename = "ControlAvailable")]
+    pub control_available: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemInfo {
+    #[serde(default, rename = "Swarm")]
+    pub swarm: SwarmInfo,
+}
+
+/// Version of the daemon and of its api
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DockerVersion {
+    #[serde(rename = "Version")]
+    pub version: String,
+    #[serde(rename = "ApiVersion")]
+    pub api_version: String,
+    #[serde(default, rename = "Os")]
+    pub os: String,
+    #[serde(default, rename = "Arch")]
+    pub arch: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceCreateResponse {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Warning")]
+    pub warning: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Service {
     #[serde(rename = "ID")]
     pub id: String,
@@ -268,11 +407,14 @@ pub struct Service {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "UpdateStatus")]
     pub update_status: Option<ServiceUpdateStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ServiceStatus")]
+    pub service_status: Option<ServiceStatus>,
     #[serde(skip)]
     pub(super) service_http_url: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceResume {
     pub id: String,
     pub version: u64,
@@ -285,3 +427,254 @@ pub struct ServiceResume {
     #[serde(rename = "fromTag")]
     pub tag: String,
 }
+
+/// Resume of a service with its deployment state
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceDetails {
+    #[serde(flatten)]
+    pub resume: ServiceResume,
+    pub digest: Option<String>,
+    pub stack: Option<String>,
+    pub mode: String,
+    pub replicas: Option<u64>,
+    #[serde(rename = "runningTasks")]
+    pub running_tasks: Option<u64>,
+    #[serde(rename = "desiredTasks")]
+    pub desired_tasks: Option<u64>,
+    #[serde(rename = "updateStatus")]
+    pub update_status: Option<ServiceUpdateStatus>,
+}
+
+/// Change of the update (or rollback) config of the service, for the rollout
+/// of a single update
+///
+/// Once the rollout completed, the config of the service is put back (see
+/// [RolloutConfig]). This second update of the spec is the previous spec of
+/// the service: a later `/rollback` returns to the changed config, not to the
+/// previous image.
+///
+/// `delay` and `monitor` are in seconds.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateConfigChange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parallelism: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure_action: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_failure_ratio: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<String>,
+}
+
+/// The update and rollback configs of a service before an update changing
+/// them, put back once its rollout completed
+#[derive(Debug, Clone, PartialEq)]
+pub struct RolloutConfig {
+    pub update_config: Option<ServiceSpecUpdateConfig>,
+    pub rollback_config: Option<ServiceSpecRollbackConfig>,
+    /// Start of the last rollout before the update, to tell it from the new one
+    pub previous_rollout: Option<DateTime<Utc>>,
+}
+
+/// Keys to set and to unset in a map (environment variables or labels)
+///
+/// Every key being unset must exist.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeyValueChanges {
+    #[serde(default)]
+    pub set: HashMap<String, String>,
+    #[serde(default)]
+    pub unset: Vec<String>,
+}
+
+/// Changes applied to the service spec along with the new image
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServiceUpdateOptions {
+    /// Changes of the container environment variables
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub env: Option<KeyValueChanges>,
+    /// Changes of the service labels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<KeyValueChanges>,
+    /// Change of the update config for this update, see [UpdateConfigChange]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_config: Option<UpdateConfigChange>,
+    /// Change of the rollback config for this update, see [UpdateConfigChange]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rollback_config: Option<UpdateConfigChange>,
+}
+
+impl ServiceUpdateOptions {
+    /// The update changes the update or the rollback config for its rollout
+    pub fn changes_rollout(&self) -> bool {
+        self.update_config.is_some() || self.rollback_config.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskContainerStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ContainerID")]
+    pub container_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "PID")]
+    pub pid: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ExitCode")]
+    pub exit_code: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskStatus {
+    #[serde(rename = "Timestamp")]
+    pub timestamp: DateTime<Utc>,
+    #[serde(rename = "State")]
+    pub state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Message")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Err")]
+    pub err: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ContainerStatus")]
+    pub container_status: Option<TaskContainerStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Task {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Version")]
+    pub version: ServiceVersion,
+    #[serde(rename = "CreatedAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "UpdatedAt")]
+    pub updated_at: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Labels")]
+    pub labels: Option<HashMap<String, String>>,
+    #[serde(rename = "Spec")]
+    pub spec: ServiceTaskTemplate,
+    #[serde(rename = "ServiceID")]
+    pub service_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Slot")]
+    pub slot: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "NodeID")]
+    pub node_id: Option<String>,
+    #[serde(rename = "Status")]
+    pub status: TaskStatus,
+    #[serde(rename = "DesiredState")]
+    pub desired_state: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeSpec {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Name")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Labels")]
+    pub labels: Option<HashMap<String, String>>,
+    #[serde(rename = "Role")]
+    pub role: String,
+    #[serde(rename = "Availability")]
+    pub availability: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeDescriptionPlatform {
+    #[serde(rename = "Architecture")]
+    pub architecture: String,
+    #[serde(rename = "OS")]
+    pub os: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeDescriptionEngine {
+    #[serde(rename = "EngineVersion")]
+    pub engine_version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeDescription {
+    #[serde(rename = "Hostname")]
+    pub hostname: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Platform")]
+    pub platform: Option<NodeDescriptionPlatform>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Engine")]
+    pub engine: Option<NodeDescriptionEngine>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeStatus {
+    #[serde(rename = "State")]
+    pub state: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Message")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Addr")]
+    pub addr: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeManagerStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "Leader")]
+    pub leader: Option<bool>,
+    #[serde(rename = "Reachability")]
+    pub reachability: String,
+    #[serde(rename = "Addr")]
+    pub addr: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Node {
+    #[serde(rename = "ID")]
+    pub id: String,
+    #[serde(rename = "Version")]
+    pub version: ServiceVersion,
+    #[serde(rename = "CreatedAt")]
+    pub created_at: DateTime<Utc>,
+    #[serde(rename = "UpdatedAt")]
+    pub updated_at: DateTime<Utc>,
+    #[serde(rename = "Spec")]
+    pub spec: NodeSpec,
+    #[serde(rename = "Description")]
+    pub description: NodeDescription,
+    #[serde(rename = "Status")]
+    pub status: NodeStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "ManagerStatus")]
+    pub manager_status: Option<NodeManagerStatus>,
+}
+
+/// Resume of a task of a service, with the node it runs on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskResume {
+    pub id: String,
+    pub slot: Option<u64>,
+    #[serde(rename = "nodeId")]
+    pub node_id: Option<String>,
+    pub node: Option<String>,
+    pub state: String,
+    #[serde(rename = "desiredState")]
+    pub desired_state: String,
+    pub message: Option<String>,
+    pub error: Option<String>,
+    #[serde(rename = "exitCode")]
+    pub exit_code: Option<i64>,
+    pub image: String,
+    pub digest: Option<String>,
+    #[serde(rename = "updatedAt")]
+    pub updated_at: DateTime<Utc>,
+}
@@ -0,0 +1,355 @@
+//! # Audit history
+//!
+//! Every change done by the updater, and every change done by someone else
+//! (detected from the docker events), is recorded here. The last entries are
+//! kept in memory and, when a file is configured, appended to it as json lines.
+//!
+use std::{
+    collections::VecDeque,
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Actor of the changes not done by the updater
+pub const EXTERNAL_ACTOR: &str = "external";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryAction {
+    Create,
+    Update,
+    Rollback,
+    Approve,
+    Reject,
+    Remove,
+    /// One-off job run from a service
+    Job,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: Uuid,
+    pub time: DateTime<Utc>,
+    pub action: HistoryAction,
+    #[serde(rename = "serviceId")]
+    pub service_id: String,
+    pub service: String,
+    #[serde(rename = "fromImage")]
+    pub from_image: Option<String>,
+    #[serde(rename = "toImage")]
+    pub to_image: Option<String>,
+    /// Name of the token, or `external`
+    pub actor: String,
+    pub external: bool,
+    pub transaction: Option<String>,
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    pub fn new(action: HistoryAction, service_id: &str, service: &str, actor: &str) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            time: Utc::now(),
+            action,
+            service_id: service_id.to_owned(),
+            service: service.to_owned(),
+            from_image: None,
+            to_image: None,
+            actor: actor.to_owned(),
+            external: actor == EXTERNAL_ACTOR,
+            transaction: None,
+            error: None,
+        }
+    }
+    pub fn with_images(mut self, from: Option<&str>, to: Option<&str>) -> Self {
+        self.from_image = from.map(|image| image.to_owned());
+        self.to_image = to.map(|image| image.to_owned());
+        self
+    }
+    pub fn with_transaction(mut self, transaction: &Uuid) -> Self {
+        self.transaction = Some(transaction.to_string());
+        self
+    }
+    pub fn with_error(mut self, error: &impl ToString) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+/// History of the changes, the newest last
+pub struct History {
+    size: usize,
+    file: Option<PathBuf>,
+    entries: Mutex<VecDeque<HistoryEntry>>,
+    /// Entries waiting to be appended to the file, see [History::record]
+    writes: Arc<Mutex<Writes>>,
+    /// Changes being done by the updater, see [History::expect]
+    expected: Mutex<Vec<ExpectedChange>>,
+}
+
+#[derive(Default)]
+struct Writes {
+    queue: VecDeque<HistoryEntry>,
+    /// A blocking task is appending the queue
+    writing: bool,
+}
+
+/// A change the updater is doing on a service
+struct ExpectedChange {
+    id: Uuid,
+    service_id: String,
+    image: String,
+}
+
+/// Registration of a change, removed when dropped (once it is recorded)
+pub struct Expected<'a> {
+    history: &'a History,
+    id: Uuid,
+}
+
+impl Drop for Expected<'_> {
+    fn drop(&mut self) {
+        self.history
+            .expected
+            .lock()
+            .unwrap()
+            .retain(|change| change.id != self.id);
+    }
+}
+
+/// Check if the image reported by docker is the image of the change
+///
+/// Docker appends the digest to the image, so `image` may be longer than
+/// the image of the change.
+fn same_image(image: &str, to: &str) -> bool {
+    let to = to.split('@').next().unwrap_or(to);
+    image == to || image.starts_with(&format!("{}@", to))
+}
+
+impl History {
+    /// Create the history keeping `size` entries in memory
+    ///
+    /// When `file` is given, the entries already in it are loaded.
+    pub fn new(size: usize, file: Option<&Path>) -> Self {
+        let entries = file.map(|file| Self::load(file, size)).unwrap_or_default();
+        History {
+            size,
+            file: file.map(|file| file.to_owned()),
+            entries: Mutex::new(entries),
+            writes: Arc::default(),
+            expected: Mutex::default(),
+        }
+    }
+
+    /// Read the last `size` entries of a history file
+    pub fn load(file: &Path, size: usize) -> VecDeque<HistoryEntry> {
+        let mut entries = VecDeque::new();
+        let Ok(content) = std::fs::File::open(file) else {
+            return entries;
+        };
+        for line in BufReader::new(content).lines().map_while(Result::ok) {
+            match serde_json::from_str::<HistoryEntry>(&line) {
+                Ok(entry) => {
+                    if entries.len() == size {
+                        entries.pop_front();
+                    }
+                    entries.push_back(entry);
+                }
+                Err(e) => warn!("Invalid history entry in {}: {}", file.display(), e),
+            }
+        }
+        entries
+    }
+
+    /// Record the entry, appending it to the file without blocking the runtime
+    pub fn record(&self, entry: HistoryEntry) {
+        if let Some(file) = &self.file {
+            self.write(file, entry.clone());
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.size {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Queue the entry for the file, appended in order by a single blocking
+    /// task (or at once, outside the runtime)
+    fn write(&self, file: &Path, entry: HistoryEntry) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return Self::append(file, &entry);
+        };
+        let mut writes = self.writes.lock().unwrap();
+        writes.queue.push_back(entry);
+        if writes.writing {
+            return;
+        }
+        writes.writing = true;
+        let writes = self.writes.clone();
+        let file = file.to_owned();
+        runtime.spawn_blocking(move || loop {
+            let queue = {
+                let mut writes = writes.lock().unwrap();
+                if writes.queue.is_empty() {
+                    writes.writing = false;
+                    return;
+                }
+                std::mem::take(&mut writes.queue)
+            };
+            for entry in queue {
+                Self::append(&file, &entry);
+            }
+        });
+    }
+
+    fn append(file: &Path, entry: &HistoryEntry) {
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file)
+            .and_then(|mut f| writeln!(f, "{}", serde_json::to_string(entry)?));
+        if let Err(e) = result {
+            warn!("Error writing history to {}: {}", file.display(), e);
+        }
+    }
+
+    /// Register a change of the service to `image` before calling docker
+    ///
+    /// Docker may report the change before it is recorded: until the
+    /// registration is dropped, [updated_by_us](History::updated_by_us) matches
+    /// it.
+    pub fn expect(&self, service_id: &str, image: &str) -> Expected<'_> {
+        let id = Uuid::new_v4();
+        self.expected.lock().unwrap().push(ExpectedChange {
+            id,
+            service_id: service_id.to_owned(),
+            image: image.to_owned(),
+        });
+        Expected { history: self, id }
+    }
+
+    /// List the entries, optionally of a single service (id or name)
+    pub fn list(&self, service: Option<&str>) -> Vec<HistoryEntry> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| {
+                service
+                    .is_none_or(|service| entry.service == service || entry.service_id == service)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Check if the updater itself changed the service to `image` since `since`,
+    /// or is changing it
+    pub fn updated_by_us(&self, service_id: &str, image: &str, since: DateTime<Utc>) -> bool {
+        let expected = self
+            .expected
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|change| change.service_id == service_id && same_image(image, &change.image));
+        expected
+            || self.entries.lock().unwrap().iter().rev().any(|entry| {
+                !entry.external
+                    && entry.error.is_none()
+                    && matches!(
+                        entry.action,
+                        HistoryAction::Update | HistoryAction::Rollback | HistoryAction::Approve
+                    )
+                    && entry.time >= since
+                    && entry.service_id == service_id
+                    && entry
+                        .to_image
+                        .as_ref()
+                        .is_some_and(|to| same_image(image, to))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_size_and_filter() {
+        let history = History::new(2, None);
+        for service in ["a", "b", "a"] {
+            history.record(HistoryEntry::new(
+                HistoryAction::Update,
+                service,
+                service,
+                "github",
+            ));
+        }
+        assert_eq!(history.list(None).len(), 2);
+        assert_eq!(history.list(Some("a")).len(), 1);
+    }
+
+    #[test]
+    fn test_updated_by_us() {
+        let history = History::new(10, None);
+        let since = Utc::now() - chrono::Duration::minutes(1);
+        history.record(
+            HistoryEntry::new(HistoryAction::Update, "id1", "web", "github")
+                .with_images(Some("nginx:1.26"), Some("nginx:1.27")),
+        );
+        assert!(history.updated_by_us("id1", "nginx:1.27@sha256:abcd", since));
+        assert!(!history.updated_by_us("id1", "nginx:1.28@sha256:abcd", since));
+        assert!(!history.updated_by_us("id2", "nginx:1.27", since));
+
+        // reported by docker before the update is recorded
+        let expected = history.expect("id2", "nginx:1.27");
+        assert!(history.updated_by_us("id2", "nginx:1.27@sha256:abcd", since));
+        drop(expected);
+        assert!(!history.updated_by_us("id2", "nginx:1.27@sha256:abcd", since));
+    }
+
+    #[test]
+    fn test_history_file() {
+        let file = std::env::temp_dir().join(format!("updater-history-{}.jsonl", Uuid::new_v4()));
+        let history = History::new(10, Some(&file));
+        history.record(HistoryEntry::new(
+            HistoryAction::Rollback,
+            "id1",
+            "web",
+            "github",
+        ));
+        let loaded = History::new(10, Some(&file));
+        assert_eq!(loaded.list(None)[0].action, HistoryAction::Rollback);
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_history_file_in_order() {
+        let file = std::env::temp_dir().join(format!("updater-history-{}.jsonl", Uuid::new_v4()));
+        let history = History::new(10, Some(&file));
+        for service in ["a", "b", "c"] {
+            history.record(HistoryEntry::new(
+                HistoryAction::Update,
+                service,
+                service,
+                "github",
+            ));
+        }
+        // appended by a blocking task
+        while history.writes.lock().unwrap().writing {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let services = History::load(&file, 10)
+            .into_iter()
+            .map(|entry| entry.service)
+            .collect::<Vec<String>>();
+        assert_eq!(services, vec!["a", "b", "c"]);
+        std::fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+//! # Update hooks
+//!
+//! Hooks run before (`pre`) and after (`post`) the update of the matching
+//! services. A hook is an http call or a one-off job (see [jobs](super::jobs))
+//! running a command with the new image, like the database migrations. A
+//! failing `pre` hook aborts the update; a failing `post` hook is only logged,
+//! as the service is already updated.
+//!
+//! The `pre` hooks of every service of an update run before the first service
+//! is updated. The update keeps running when its request times out, so a long
+//! job hook is not stopped halfway.
+//!
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{
+    docker::{error::DockerError, types::Service, Docker},
+    jobs,
+    redact::Secret,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HookStage {
+    Pre,
+    Post,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HookAction {
+    /// Call the url with the service and the new image as json
+    Http {
+        url: String,
+        #[serde(default = "default_method")]
+        method: String,
+        #[serde(default)]
+        headers: HashMap<String, Secret>,
+    },
+    /// Run the command as a job, with the spec of the service and the new image
+    Job { command: Vec<String> },
+}
+
+fn default_method() -> String {
+    "POST".to_owned()
+}
+
+fn default_timeout() -> u64 {
+    300
+}
+
+/// A hook of the services matching by name or by label
+///
+/// ```json
+/// "hooks": [
+///   { "name": "migrate", "stage": "pre", "services": ["shop_api"], "type": "job", "command": ["./migrate"] },
+///   { "name": "purge", "stage": "post", "labels": { "cdn": "true" }, "type": "http", "url": "https://cdn.usign.io/purge" }
+/// ]
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hook {
+    pub name: String,
+    pub stage: HookStage,
+    /// Names of the services
+    #[serde(default)]
+    pub services: Vec<String>,
+    /// Labels of the services, any matching label is enough
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(flatten)]
+    pub action: HookAction,
+    /// Time (in seconds) to wait for the hook
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+impl Hook {
+    pub fn matches(&self, service: &Service) -> bool {
+        self.services.contains(&service.spec.name)
+            || self
+                .labels
+                .iter()
+                .any(|(key, value)| service.label(key) == Some(value.as_str()))
+    }
+
+    /// Check the hook can match something and run something
+    pub fn validate(&self) -> Result<(), String> {
+        if self.services.is_empty() && self.labels.is_empty() {
+            return Err(format!("hook {}: no services or labels", self.name));
+        }
+        if let HookAction::Job { command } = &self.action {
+            if command.is_empty() {
+                return Err(format!("hook {}: empty command", self.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The configured hooks
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    client: reqwest::Client,
+}
+
+impl Hooks {
+    pub fn new(hooks: Vec<Hook>) -> Self {
+        Self {
+            hooks,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Run the `pre` hooks of the service, stopping on the first failure
+    pub async fn before(
+        &self,
+        docker: &Docker,
+        service: &Service,
+        image: &str,
+        tag: &str,
+    ) -> Result<(), DockerError> {
+        for hook in self.matching(HookStage::Pre, service) {
+            self.run(hook, docker, service, image, tag).await?;
+        }
+        Ok(())
+    }
+
+    /// Run the `post` hooks of the service, logging the failures
+    pub async fn after(&self, docker: &Docker, service: &Service, image: &str, tag: &str) {
+        for hook in self.matching(HookStage::Post, service) {
+            if let Err(e) = self.run(hook, docker, service, image, tag).await {
+                warn!("Post hook of {} failed: {}", service.spec.name, e);
+            }
+        }
+    }
+
+    fn matching<'a>(
+        &'a self,
+        stage: HookStage,
+        service: &'a Service,
+    ) -> impl Iterator<Item = &'a Hook> {
+        self.hooks
+            .iter()
+            .filter(move |hook| hook.stage == stage && hook.matches(service))
+    }
+
+    async fn run(
+        &self,
+        hook: &Hook,
+        docker: &Docker,
+        service: &Service,
+        image: &str,
+        tag: &str,
+    ) -> Result<(), DockerError> {
+        info!(
+            "Running hook {} of {} for {}:{}",
+            hook.name, service.spec.name, image, tag
+        );
+        let timeout = Duration::from_secs(hook.timeout);
+        match &hook.action {
+            HookAction::Http {
+                url,
+                method,
+                headers,
+            } => {
+                let method = reqwest::Method::from_bytes(method.as_bytes()).map_err(|_| {
+                    DockerError::HookError(format!("{}: invalid method {}", hook.name, method))
+                })?;
+                let mut request = self
+                    .client
+                    .request(method, url)
+                    .timeout(timeout)
+                    .json(&json!({
+                        "hook": hook.name,
+                        "stage": hook.stage,
+                        "serviceId": service.id,
+                        "service": service.spec.name,
+                        "image": image,
+                        "tag": tag,
+                    }));
+                for (name, value) in headers {
+                    request = request.header(name, value.expose());
+                }
+                let response = request
+                    .send()
+                    .await
+                    .map_err(|e| DockerError::HookError(format!("{}: {}", hook.name, e)))?;
+                if !response.status().is_success() {
+                    return Err(DockerError::HookError(format!(
+                        "{}: {} returned {}",
+                        hook.name,
+                        url,
+                        response.status()
+                    )));
+                }
+            }
+            HookAction::Job { command } => {
+                let spec = jobs::job_spec(
+                    service,
+                    // unique, for the concurrent updates of the service
+                    &format!(
+                        "{}-{}-{}",
+                        service.spec.name,
+                        hook.name,
+                        &Uuid::new_v4().simple().to_string()[..8]
+                    ),
+                    &format!("{}:{}", image, tag),
+                    Some(command),
+                );
+                let result = jobs::run(docker, &spec, timeout).await?;
+                if !result.succeeded() {
+                    return Err(DockerError::HookError(format!(
+                        "{}: job {} with exit code {:?} {}",
+                        hook.name,
+                        result.state,
+                        result.exit_code,
+                        result.error.unwrap_or_default()
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::notify::tests::stand_in;
+
+    fn service(name: &str, labels: serde_json::Value) -> Service {
+        serde_json::from_value(json!({
+            "ID": name,
+            "Version": { "Index": 1 },
+            "CreatedAt": "2024-06-01T10:00:00Z",
+            "UpdatedAt": "2024-06-01T10:00:00Z",
+            "Spec": {
+                "Name": name,
+                "Labels": labels,
+                "TaskTemplate": { "ContainerSpec": { "Image": "shop/api:1.0" } }
+            }
+        }))
+        .unwrap()
+    }
+
+    fn hook(stage: HookStage, url: &str) -> Hook {
+        serde_json::from_value(json!({
+            "name": "purge",
+            "stage": stage,
+            "labels": { "cdn": "true" },
+            "type": "http",
+            "url": url,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_matches() {
+        let hook = hook(HookStage::Post, "http://localhost");
+        assert!(hook.matches(&service("web", json!({ "cdn": "true" }))));
+        assert!(!hook.matches(&service("web", json!({ "cdn": "false" }))));
+        assert!(hook.validate().is_ok());
+        let hook = Hook {
+            labels: HashMap::new(),
+            ..hook
+        };
+        assert!(hook.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_hook() {
+        let (url, received) = stand_in().await;
+        let hooks = Hooks::new(vec![
+            hook(HookStage::Pre, &format!("{}/pre", url)),
+            hook(HookStage::Post, &format!("{}/post", url)),
+        ]);
+        let docker = Docker::new(&url);
+        let service = service("web", json!({ "cdn": "true" }));
+        hooks
+            .before(&docker, &service, "shop/api", "2.0")
+            .await
+            .unwrap();
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].path, "/pre");
+        assert_eq!(received[0].body["tag"], "2.0");
+        assert_eq!(received[0].body["stage"], "pre");
+    }
+}
@@ -0,0 +1,212 @@
+//! # One-off jobs
+//!
+//! A job is a temporary `ReplicatedJob` service built from the spec of an
+//! existing service: it keeps the networks, mounts, configs and env, but runs
+//! another image or command once, without restarts. The logs of the job are
+//! collected and the job service is removed when its task finishes.
+//!
+use std::time::Duration;
+
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::time::{sleep, Instant};
+use tracing::{info, warn};
+
+use super::docker::{
+    error::DockerError,
+    logs::{LogFrame, LogsOptions},
+    types::{
+        Service, ServiceSpec, ServiceSpecMode, ServiceSpecModeReplicatedJob,
+        ServiceTaskTemplateRestartPolicy, Task,
+    },
+    Docker,
+};
+
+/// Label added to the job services, with the name of the original service
+pub const JOB_LABEL: &str = "updater.job";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Final states of a task
+const FINAL_STATES: [&str; 5] = ["complete", "failed", "rejected", "shutdown", "orphaned"];
+
+/// Result of a finished job
+#[derive(Debug, Clone, Serialize)]
+pub struct JobResult {
+    pub state: String,
+    #[serde(rename = "exitCode")]
+    pub exit_code: Option<i64>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+    pub logs: Vec<LogFrame>,
+}
+
+impl JobResult {
+    pub fn succeeded(&self) -> bool {
+        self.state == "complete" && self.exit_code.unwrap_or(0) == 0
+    }
+}
+
+impl From<&Task> for JobResult {
+    fn from(task: &Task) -> Self {
+        Self {
+            state: task.status.state.clone(),
+            exit_code: task
+                .status
+                .container_status
+                .as_ref()
+                .and_then(|status| status.exit_code),
+            message: task.status.message.clone(),
+            error: task.status.err.clone(),
+            logs: vec![],
+        }
+    }
+}
+
+/// Build the spec of a job named `name` from `service`, running `image`
+///
+/// When `command` is given, it replaces the command and the args of the image.
+pub fn job_spec(
+    service: &Service,
+    name: &str,
+    image: &str,
+    command: Option<&[String]>,
+) -> ServiceSpec {
+    let mut spec = service.spec.clone();
+    spec.name = name.to_owned();
+    spec.mode = Some(ServiceSpecMode {
+        replicated_job: Some(ServiceSpecModeReplicatedJob {
+            max_concurrent: Some(1),
+            total_completions: Some(1),
+        }),
+        ..Default::default()
+    });
+    spec.endpoint_spec = None;
+    spec.update_config = None;
+    spec.rollback_config = None;
+    let container_spec = &mut spec.task_template.container_spec;
+    container_spec.image = image.to_owned();
+    container_spec.health_check = None;
+    if let Some(command) = command {
+        container_spec.command = Some(command.to_vec());
+        container_spec.args = None;
+    }
+    spec.task_template.restart_policy = Some(ServiceTaskTemplateRestartPolicy {
+        condition: Some("none".into()),
+        ..Default::default()
+    });
+    spec.labels
+        .get_or_insert_with(Default::default)
+        .insert(JOB_LABEL.into(), service.spec.name.clone());
+    spec
+}
+
+/// Run the job, waiting up to `timeout` for it to finish
+///
+/// The job service is always removed, also when the future is dropped.
+pub async fn run(
+    docker: &Docker,
+    spec: &ServiceSpec,
+    timeout: Duration,
+) -> Result<JobResult, DockerError> {
+    let job = docker.service_create_temporary(spec, None).await?;
+    info!(
+        "Job {} created with {}",
+        spec.name, spec.task_template.container_spec.image
+    );
+    let mut result = wait_finished(docker, &job.id, timeout).await;
+    if let Ok(result) = result.as_mut() {
+        result.logs = logs(docker, &job.id).await;
+    }
+    job.remove().await;
+    result
+}
+
+/// Logs of the finished job, empty when the log driver can't read them
+async fn logs(docker: &Docker, id: &str) -> Vec<LogFrame> {
+    match docker.service_logs(id, &LogsOptions::default()).await {
+        Ok(frames) => {
+            frames
+                .filter_map(|frame| async move { frame.ok() })
+                .collect()
+                .await
+        }
+        Err(e) => {
+            warn!("Error reading the logs of job {}: {}", id, e);
+            vec![]
+        }
+    }
+}
+
+async fn wait_finished(
+    docker: &Docker,
+    id: &str,
+    timeout: Duration,
+) -> Result<JobResult, DockerError> {
+    let started_at = Instant::now();
+    loop {
+        let tasks = docker.tasks_list(Some(id)).await?;
+        if let Some(task) = tasks
+            .iter()
+            .find(|task| FINAL_STATES.contains(&task.status.state.as_str()))
+        {
+            return Ok(JobResult::from(task));
+        }
+        if started_at.elapsed() >= timeout {
+            return Err(DockerError::JobError(format!(
+                "Job not finished after {}s",
+                timeout.as_secs()
+            )));
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_job_spec() {
+        let service: Service = serde_json::from_value(json!({
+            "ID": "abc",
+            "Version": { "Index": 10 },
+            "CreatedAt": "2024-06-01T10:00:00Z",
+            "UpdatedAt": "2024-06-01T10:00:00Z",
+            "Spec": {
+                "Name": "shop_api",
+                "TaskTemplate": {
+                    "ContainerSpec": {
+                        "Image": "shop/api:1.0",
+                        "Args": ["serve"],
+                        "Env": ["DATABASE_URL=postgres://db"],
+                        "Secrets": [{ "SecretID": "s1", "SecretName": "db_password" }]
+                    },
+                    "Networks": [{ "Target": "backend" }]
+                },
+                "Mode": { "Replicated": { "Replicas": 3 } },
+                "EndpointSpec": {
+                    "Ports": [{ "Protocol": "tcp", "TargetPort": 80, "PublishedPort": 80 }]
+                }
+            }
+        }))
+        .unwrap();
+        let command = vec!["migrate".to_owned()];
+        let spec = job_spec(&service, "shop_api-migrate", "shop/api:2.0", Some(&command));
+        let value = serde_json::to_value(&spec).unwrap();
+        assert_eq!(value["Name"], "shop_api-migrate");
+        assert_eq!(value["Mode"]["ReplicatedJob"]["TotalCompletions"], 1);
+        assert!(value["Mode"]["Replicated"].is_null());
+        assert!(value["EndpointSpec"].is_null());
+        let container_spec = &value["TaskTemplate"]["ContainerSpec"];
+        assert_eq!(container_spec["Image"], "shop/api:2.0");
+        assert_eq!(container_spec["Command"], json!(["migrate"]));
+        assert!(container_spec["Args"].is_null());
+        assert_eq!(container_spec["Env"][0], "DATABASE_URL=postgres://db");
+        assert_eq!(container_spec["Secrets"][0]["SecretName"], "db_password");
+        assert_eq!(value["TaskTemplate"]["Networks"][0]["Target"], "backend");
+        assert_eq!(value["TaskTemplate"]["RestartPolicy"]["Condition"], "none");
+        assert_eq!(value["Labels"][JOB_LABEL], "shop_api");
+    }
+}
@@ -1 +1,12 @@
+pub mod approvals;
+pub mod background;
+pub mod canary;
 pub mod docker;
+pub mod history;
+pub mod hooks;
+pub mod jobs;
+pub mod notify;
+pub mod prepull;
+pub mod redact;
+pub mod registry;
+pub mod watcher;
@@ -0,0 +1,227 @@
+use async_trait::async_trait;
+use lettre::{
+    message::header::ContentType,
+    transport::smtp::{authentication::Credentials, AsyncSmtpTransport},
+    AsyncTransport, Message, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{error::NotifyError, Notification, Notifier};
+
+/// Security of the connection with the SMTP server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Plain connection upgraded with STARTTLS (port 587)
+    #[default]
+    Starttls,
+    /// TLS from the start (port 465)
+    Tls,
+    /// No encryption, only for local relays
+    None,
+}
+
+/// Send the summary of the notification by email
+pub struct EmailNotifier {
+    host: String,
+    port: Option<u16>,
+    security: SmtpSecurity,
+    credentials: Option<Credentials>,
+    from: String,
+    to: Vec<String>,
+}
+
+impl EmailNotifier {
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        security: SmtpSecurity,
+        from: &str,
+        to: &[String],
+    ) -> Self {
+        Self {
+            host: host.to_owned(),
+            port,
+            security,
+            credentials: None,
+            from: from.to_owned(),
+            to: to.to_vec(),
+        }
+    }
+
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.credentials = Some(Credentials::new(username.to_owned(), password.to_owned()));
+        self
+    }
+
+    fn transport(&self) -> Result<AsyncSmtpTransport<Tokio1Executor>, NotifyError> {
+        let mut builder = match self.security {
+            SmtpSecurity::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&self.host)?
+            }
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&self.host)?,
+            SmtpSecurity::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&self.host)
+            }
+        };
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+        if let Some(credentials) = &self.credentials {
+            builder = builder.credentials(credentials.clone());
+        }
+        Ok(builder.build())
+    }
+
+    fn message(
+        &self,
+        notification: &Notification,
+        message: Option<&str>,
+    ) -> Result<Message, NotifyError> {
+        let mut builder = Message::builder()
+            .from(self.from.parse()?)
+            .subject(format!(
+                "[{}] {} {}",
+                notification.outcome.as_str(),
+                notification.kind.as_str(),
+                notification.transaction
+            ))
+            .header(ContentType::TEXT_PLAIN);
+        for to in &self.to {
+            builder = builder.to(to.parse()?);
+        }
+        let body = message.map_or_else(|| body(notification), str::to_owned);
+        Ok(builder.body(body)?)
+    }
+}
+
+/// Body of the email: the summary with the details of each service
+fn body(notification: &Notification) -> String {
+    let mut lines = vec![
+        format!(
+            "The {} requested by {} finished with {}.",
+            notification.kind.as_str(),
+            notification.actor,
+            notification.outcome.as_str()
+        ),
+        String::new(),
+        format!("Transaction: {}", notification.transaction),
+        format!("Started at: {}", notification.started_at.to_rfc3339()),
+        format!(
+            "Duration: {}s",
+            (notification.finished_at - notification.started_at).num_seconds()
+        ),
+        String::new(),
+        "Services:".to_owned(),
+    ];
+    for change in &notification.changes {
+        lines.push(format!(
+            "- {} ({}): {}:{} -> {}:{}",
+            change.service,
+            change.stack.as_deref().unwrap_or("no stack"),
+            change.old.image,
+            change.old.tag,
+            change.new.image,
+            change.new.tag
+        ));
+        if let Some(error) = &change.error {
+            lines.push(format!("  Error: {}", error));
+        }
+    }
+    if let Some(error) = &notification.error {
+        lines.push(String::new());
+        lines.push(format!("Error: {}", error));
+    }
+    lines.join("\n")
+}
+
+#[async_trait]
+impl Notifier for EmailNotifier {
+    async fn notify(
+        &self,
+        notification: &Notification,
+        message: Option<&str>,
+    ) -> Result<(), NotifyError> {
+        self.transport()?
+            .send(self.message(notification, message)?)
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::notify::tests::notification;
+    use std::sync::{Arc, Mutex};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// Local SMTP server recording the commands and the messages received
+    async fn smtp_sink() -> (u16, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let received = Arc::new(Mutex::new(vec![]));
+        let lines = received.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut reader = BufReader::new(read).lines();
+            write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+            let mut data: Option<String> = None;
+            while let Ok(Some(line)) = reader.next_line().await {
+                if let Some(message) = data.as_mut() {
+                    if line == "." {
+                        lines.lock().unwrap().push(data.take().unwrap());
+                        write.write_all(b"250 OK\r\n").await.unwrap();
+                    } else {
+                        *message += &format!("{}\n", line);
+                    }
+                    continue;
+                }
+                lines.lock().unwrap().push(line.clone());
+                let command = line.to_uppercase();
+                let reply = if command.starts_with("EHLO") {
+                    "250-localhost\r\n250 AUTH PLAIN\r\n"
+                } else if command.starts_with("AUTH") {
+                    "235 Authenticated\r\n"
+                } else if command.starts_with("DATA") {
+                    data = Some(String::new());
+                    "354 End with .\r\n"
+                } else if command.starts_with("QUIT") {
+                    "221 Bye\r\n"
+                } else {
+                    "250 OK\r\n"
+                };
+                write.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        (port, received)
+    }
+
+    #[tokio::test]
+    async fn test_email() {
+        let (port, received) = smtp_sink().await;
+        let notifier = EmailNotifier::new(
+            "127.0.0.1",
+            Some(port),
+            SmtpSecurity::None,
+            "updater@example.org",
+            &["ops@example.org".to_owned()],
+        )
+        .with_credentials("updater", "secret");
+        notifier.notify(&notification(), None).await.unwrap();
+        let received = received.lock().unwrap();
+        assert!(received.iter().any(|line| line.starts_with("AUTH PLAIN")));
+        assert!(received.contains(&"RCPT TO:<ops@example.org>".to_owned()));
+        let message = received
+            .iter()
+            .find(|line| line.contains("Subject:"))
+            .unwrap();
+        assert!(message.contains("Subject: [success] release t1"));
+        assert!(message.contains("Transaction: t1"));
+        assert!(message.contains("- shop_api (shop): shop/api:1.0 -> shop/api:2.0"));
+    }
+}
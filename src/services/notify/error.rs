@@ -0,0 +1,30 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NotifyError {
+    #[error("Notification request error: {0}")]
+    RequestError(#[from] reqwest::Error),
+    #[error("Notification rejected: {0}")]
+    Rejected(String),
+    #[error("Invalid email address: {0}")]
+    AddressError(#[from] lettre::address::AddressError),
+    #[error("Invalid email: {0}")]
+    EmailError(#[from] lettre::error::Error),
+    #[error("SMTP error: {0}")]
+    SmtpError(#[from] lettre::transport::smtp::Error),
+    #[error("Template error: {0}")]
+    TemplateError(String),
+}
+
+impl From<tera::Error> for NotifyError {
+    /// The message of the tera errors is in the sources
+    fn from(error: tera::Error) -> Self {
+        let mut message = error.to_string();
+        let mut source = std::error::Error::source(&error);
+        while let Some(error) = source {
+            message = format!("{}: {}", message, error);
+            source = error.source();
+        }
+        NotifyError::TemplateError(message)
+    }
+}
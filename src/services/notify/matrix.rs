@@ -0,0 +1,91 @@
+use async_trait::async_trait;
+use reqwest::Url;
+use serde_json::json;
+use uuid::Uuid;
+
+use super::{error::NotifyError, Notification, Notifier};
+
+/// Send the summary as a text message to a Matrix room
+pub struct MatrixNotifier {
+    homeserver: String,
+    room_id: String,
+    access_token: String,
+    client: reqwest::Client,
+}
+
+impl MatrixNotifier {
+    pub fn new(homeserver: &str, room_id: &str, access_token: &str) -> Self {
+        Self {
+            homeserver: homeserver.trim_end_matches('/').to_owned(),
+            room_id: room_id.to_owned(),
+            access_token: access_token.to_owned(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    fn message_url(&self) -> Result<Url, NotifyError> {
+        let mut url = Url::parse(&self.homeserver)
+            .map_err(|e| NotifyError::Rejected(format!("Invalid homeserver: {}", e)))?;
+        url.path_segments_mut()
+            .map_err(|_| NotifyError::Rejected("Invalid homeserver".into()))?
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                &self.room_id,
+                "send",
+                "m.room.message",
+                &Uuid::new_v4().to_string(),
+            ]);
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl Notifier for MatrixNotifier {
+    async fn notify(
+        &self,
+        notification: &Notification,
+        message: Option<&str>,
+    ) -> Result<(), NotifyError> {
+        let payload = json!({
+            "msgtype": "m.text",
+            "body": message.map_or_else(|| notification.summary(), str::to_owned),
+        });
+        let response = self
+            .client
+            .put(self.message_url()?)
+            .bearer_auth(&self.access_token)
+            .json(&payload)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(NotifyError::Rejected(response.text().await?));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::notify::tests::{notification, stand_in};
+
+    #[tokio::test]
+    async fn test_matrix() {
+        let (url, received) = stand_in().await;
+        let notifier = MatrixNotifier::new(&url, "!room:example.org", "token");
+        notifier.notify(&notification(), None).await.unwrap();
+        let received = received.lock().unwrap();
+        assert_eq!(received[0].method, "PUT");
+        assert!(received[0]
+            .path
+            .starts_with("/_matrix/client/v3/rooms/!room:example.org/send/m.room.message/"));
+        assert_eq!(
+            received[0].headers.get("Authorization").unwrap(),
+            "Bearer token"
+        );
+        assert_eq!(received[0].body["msgtype"], "m.text");
+    }
+}
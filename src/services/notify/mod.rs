@@ -0,0 +1,441 @@
+//! # Notifications
+//!
+//! After each update, release or rollback a [Notification] is sent to the
+//! configured sinks. Each sink has filters: a sink only receives the
+//! notifications with a matching outcome, and only the changes of the matching
+//! stacks and images.
+//!
+pub mod email;
+pub mod error;
+pub mod matrix;
+pub mod slack;
+pub mod template;
+pub mod webhook;
+
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use error::NotifyError;
+use serde::{Deserialize, Serialize};
+use template::Template;
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::{
+    docker::{split_image, types::Service, STACK_NAMESPACE_LABEL},
+    redact::Redactor,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationKind {
+    Update,
+    Release,
+    Rollback,
+}
+
+impl NotificationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationKind::Update => "update",
+            NotificationKind::Release => "release",
+            NotificationKind::Rollback => "rollback",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// The change is waiting an approval
+    Pending,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Pending => "pending",
+        }
+    }
+}
+
+/// Image of a service, before or after a change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServiceImage {
+    pub image: String,
+    pub tag: String,
+}
+
+impl ServiceImage {
+    pub fn new(image: &str, tag: &str) -> Self {
+        Self {
+            image: image.to_owned(),
+            tag: tag.to_owned(),
+        }
+    }
+}
+
+/// Change of a service, from the old to the new image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceChange {
+    pub service: String,
+    pub stack: Option<String>,
+    pub old: ServiceImage,
+    pub new: ServiceImage,
+    pub error: Option<String>,
+}
+
+impl ServiceChange {
+    /// Change of the service to `image:tag`
+    pub fn new(service: &Service, image: &str, tag: &str) -> Self {
+        let (old_image, old_tag) = split_image(&service.spec.task_template.container_spec.image);
+        Self {
+            service: service.spec.name.clone(),
+            stack: service.label(STACK_NAMESPACE_LABEL).map(str::to_owned),
+            old: ServiceImage::new(old_image, old_tag),
+            new: ServiceImage::new(image, tag),
+            error: None,
+        }
+    }
+    pub fn with_error(mut self, error: &impl ToString) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    pub outcome: Outcome,
+    pub transaction: String,
+    /// Name of the token that requested the change
+    pub actor: String,
+    #[serde(rename = "startedAt")]
+    pub started_at: DateTime<Utc>,
+    #[serde(rename = "finishedAt")]
+    pub finished_at: DateTime<Utc>,
+    pub changes: Vec<ServiceChange>,
+    pub error: Option<String>,
+    /// Body of the request of the change
+    pub request: Option<serde_json::Value>,
+}
+
+impl Notification {
+    /// Successful notification finished now, without changes
+    pub fn new(
+        kind: NotificationKind,
+        transaction: &Uuid,
+        actor: &str,
+        started_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            kind,
+            outcome: Outcome::Success,
+            transaction: transaction.to_string(),
+            actor: actor.to_owned(),
+            started_at,
+            finished_at: Utc::now(),
+            changes: vec![],
+            error: None,
+            request: None,
+        }
+    }
+    pub fn with_request(mut self, request: &impl Serialize) -> Self {
+        self.request = serde_json::to_value(request).ok();
+        self
+    }
+    pub fn with_changes(mut self, changes: Vec<ServiceChange>) -> Self {
+        self.changes = changes;
+        self
+    }
+    pub fn with_outcome(mut self, outcome: Outcome) -> Self {
+        self.outcome = outcome;
+        self
+    }
+    /// Failed notification
+    pub fn with_error(mut self, error: &impl ToString) -> Self {
+        self.outcome = Outcome::Failure;
+        self.error = Some(error.to_string());
+        self
+    }
+
+    /// Human readable summary, used by the chat sinks
+    pub fn summary(&self) -> String {
+        let mut lines = vec![format!(
+            "[{}] {} requested by {} (transaction {})",
+            self.outcome.as_str(),
+            self.kind.as_str(),
+            self.actor,
+            self.transaction
+        )];
+        for change in &self.changes {
+            let mut line = format!(
+                "- {}: {}:{} -> {}:{}",
+                change.service, change.old.image, change.old.tag, change.new.image, change.new.tag
+            );
+            if let Some(error) = &change.error {
+                line.push_str(&format!(" (error: {})", error));
+            }
+            lines.push(line);
+        }
+        if let Some(error) = &self.error {
+            lines.push(format!("Error: {}", error));
+        }
+        lines.join("\n")
+    }
+}
+
+/// A sink of notifications
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Send the notification, `message` is the rendered template of the sink
+    /// (when configured) to use instead of the default message
+    async fn notify(
+        &self,
+        notification: &Notification,
+        message: Option<&str>,
+    ) -> Result<(), NotifyError>;
+}
+
+/// Filters of a sink, an empty filter matches everything
+#[derive(Debug, Clone, Default)]
+pub struct NotificationFilter {
+    pub outcomes: Vec<Outcome>,
+    pub stacks: Vec<String>,
+    pub images: Vec<String>,
+}
+
+impl NotificationFilter {
+    /// Keep only the changes matching the filter, `None` when nothing matches
+    pub fn apply(&self, notification: &Notification) -> Option<Notification> {
+        if !self.outcomes.is_empty() && !self.outcomes.contains(&notification.outcome) {
+            return None;
+        }
+        if self.stacks.is_empty() && self.images.is_empty() {
+            return Some(notification.clone());
+        }
+        let changes = notification
+            .changes
+            .iter()
+            .filter(|change| {
+                self.stacks.is_empty()
+                    || change
+                        .stack
+                        .as_ref()
+                        .is_some_and(|stack| self.stacks.contains(stack))
+            })
+            .filter(|change| {
+                self.images.is_empty()
+                    || self.images.contains(&change.old.image)
+                    || self.images.contains(&change.new.image)
+            })
+            .cloned()
+            .collect::<Vec<ServiceChange>>();
+        if changes.is_empty() {
+            return None;
+        }
+        Some(Notification {
+            changes,
+            ..notification.clone()
+        })
+    }
+}
+
+struct Sink {
+    name: String,
+    filter: NotificationFilter,
+    template: Option<Arc<Template>>,
+    notifier: Arc<dyn Notifier>,
+}
+
+/// The configured sinks
+#[derive(Default)]
+pub struct Notifiers {
+    sinks: Vec<Sink>,
+    sending: Mutex<Vec<JoinHandle<()>>>,
+    redactor: Redactor,
+}
+
+impl Notifiers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hide the sensitive environment variables of the requests
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    pub fn with_sink(
+        mut self,
+        name: &str,
+        filter: NotificationFilter,
+        template: Option<Template>,
+        notifier: Arc<dyn Notifier>,
+    ) -> Self {
+        self.sinks.push(Sink {
+            name: name.to_owned(),
+            filter,
+            template: template.map(Arc::new),
+            notifier,
+        });
+        self
+    }
+
+    /// Send the notification to the matching sinks, in background
+    pub fn dispatch(&self, mut notification: Notification) {
+        if let Some(request) = notification.request.as_mut() {
+            self.redactor.value(request);
+        }
+        for sink in &self.sinks {
+            let Some(notification) = sink.filter.apply(&notification) else {
+                debug!("Notification filtered out by {}", sink.name);
+                continue;
+            };
+            let name = sink.name.clone();
+            let template = sink.template.clone();
+            let notifier = sink.notifier.clone();
+            let handle = tokio::spawn(async move {
+                let message = match template.map(|template| template.render(&notification)) {
+                    Some(Ok(message)) => Some(message),
+                    Some(Err(e)) => {
+                        warn!("Error rendering the template of {}: {}", name, e);
+                        None
+                    }
+                    None => None,
+                };
+                if let Err(e) = notifier.notify(&notification, message.as_deref()).await {
+                    warn!("Error sending notification to {}: {}", name, e);
+                }
+            });
+            let mut sending = self.sending.lock().unwrap();
+            sending.retain(|handle| !handle.is_finished());
+            sending.push(handle);
+        }
+    }
+
+    /// Wait for the notifications sent in background, before exiting
+    pub async fn flush(&self) {
+        let sending = std::mem::take(&mut *self.sending.lock().unwrap());
+        for handle in sending {
+            let _ = handle.await;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use axum::{
+        body::Bytes,
+        http::{HeaderMap, Method, Uri},
+        Router,
+    };
+    use std::sync::Mutex;
+
+    /// A request received by the [stand_in] server
+    #[derive(Debug, Clone)]
+    pub(crate) struct Received {
+        pub method: Method,
+        pub path: String,
+        pub headers: HeaderMap,
+        pub body: serde_json::Value,
+    }
+
+    /// Local http server recording the requests received
+    pub(crate) async fn stand_in() -> (String, Arc<Mutex<Vec<Received>>>) {
+        let received = Arc::new(Mutex::new(vec![]));
+        let requests = received.clone();
+        let app = Router::new().fallback(
+            move |method: Method, uri: Uri, headers: HeaderMap, body: Bytes| {
+                let requests = requests.clone();
+                async move {
+                    requests.lock().unwrap().push(Received {
+                        method,
+                        path: uri.to_string(),
+                        headers,
+                        body: serde_json::from_slice(&body).unwrap_or_default(),
+                    });
+                    "{}"
+                }
+            },
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    pub(crate) fn notification() -> Notification {
+        Notification {
+            kind: NotificationKind::Release,
+            outcome: Outcome::Success,
+            transaction: "t1".into(),
+            actor: "github".into(),
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+            changes: vec![
+                ServiceChange {
+                    service: "shop_api".into(),
+                    stack: Some("shop".into()),
+                    old: ServiceImage::new("shop/api", "1.0"),
+                    new: ServiceImage::new("shop/api", "2.0"),
+                    error: None,
+                },
+                ServiceChange {
+                    service: "blog_web".into(),
+                    stack: Some("blog".into()),
+                    old: ServiceImage::new("nginx", "1.26"),
+                    new: ServiceImage::new("nginx", "1.27"),
+                    error: None,
+                },
+            ],
+            error: None,
+            request: None,
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let notification = notification();
+        assert_eq!(
+            NotificationFilter::default()
+                .apply(&notification)
+                .unwrap()
+                .changes
+                .len(),
+            2
+        );
+        let filter = NotificationFilter {
+            stacks: vec!["shop".into()],
+            ..Default::default()
+        };
+        let filtered = filter.apply(&notification).unwrap();
+        assert_eq!(filtered.changes.len(), 1);
+        assert_eq!(filtered.changes[0].service, "shop_api");
+        let filter = NotificationFilter {
+            images: vec!["redis".into()],
+            ..Default::default()
+        };
+        assert!(filter.apply(&notification).is_none());
+        let filter = NotificationFilter {
+            outcomes: vec![Outcome::Failure],
+            ..Default::default()
+        };
+        assert!(filter.apply(&notification).is_none());
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = notification().summary();
+        assert!(summary.starts_with("[success] release requested by github"));
+        assert!(summary.contains("- shop_api: shop/api:1.0 -> shop/api:2.0"));
+    }
+}
@@ -0,0 +1,63 @@
+use async_trait::async_trait;
+use serde_json::json;
+
+use super::{error::NotifyError, Notification, Notifier};
+
+/// Post the summary to a Slack incoming webhook (or a compatible one)
+pub struct SlackNotifier {
+    url: String,
+    channel: Option<String>,
+    username: Option<String>,
+    client: reqwest::Client,
+}
+
+impl SlackNotifier {
+    pub fn new(url: &str, channel: Option<String>, username: Option<String>) -> Self {
+        Self {
+            url: url.to_owned(),
+            channel,
+            username,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    async fn notify(
+        &self,
+        notification: &Notification,
+        message: Option<&str>,
+    ) -> Result<(), NotifyError> {
+        let mut payload =
+            json!({ "text": message.map_or_else(|| notification.summary(), str::to_owned) });
+        if let Some(channel) = &self.channel {
+            payload["channel"] = json!(channel);
+        }
+        if let Some(username) = &self.username {
+            payload["username"] = json!(username);
+        }
+        let response = self.client.post(&self.url).json(&payload).send().await?;
+        if !response.status().is_success() {
+            return Err(NotifyError::Rejected(response.text().await?));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::notify::tests::{notification, stand_in};
+
+    #[tokio::test]
+    async fn test_slack() {
+        let (url, received) = stand_in().await;
+        let notifier = SlackNotifier::new(&url, Some("#deploys".into()), None);
+        notifier.notify(&notification(), None).await.unwrap();
+        let received = received.lock().unwrap();
+        assert_eq!(received[0].body["channel"], "#deploys");
+        assert!(received[0].body.get("username").is_none());
+        assert_eq!(received[0].body["text"], notification().summary());
+    }
+}
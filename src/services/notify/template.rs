@@ -0,0 +1,150 @@
+//! # Templates of the notifications
+//!
+//! A sink can replace its default message with a [Tera](https://keats.github.io/tera/docs/)
+//! template. The template is rendered against the notification:
+//!
+//! * kind: `update`, `release` or `rollback`
+//! * outcome: `success`, `failure` or `pending`
+//! * transaction: Id of the transaction of the change
+//! * actor: Name of the token that requested the change
+//! * request: Body of the request, depending on the kind: `image` and `tag` of
+//!   an update, `stack` and `images` of a release, `service` of a rollback (or
+//!   the release rolled back). Use `default` for the fields of a single kind,
+//!   like `{{ request.tag | default(value="") }}`
+//! * startedAt, finishedAt: Start and end of the change (RFC 3339)
+//! * duration: Duration of the change, in seconds
+//! * changes: The services changed, each with:
+//!   * service: Name of the service
+//!   * stack: Stack namespace of the service, if any
+//!   * old, new: Image of the service before and after the change (`image`, `tag`)
+//!   * error: Error of the change of this service, if any
+//! * error: Error of the change, if any
+//! * summary: The default message
+//!
+//! ```text
+//! {{ actor }} deployed {% for change in changes %}{{ change.service }}:{{ change.new.tag }} {% endfor %}({{ outcome }})
+//! ```
+//!
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::json;
+use tera::{Context, Tera};
+use uuid::Uuid;
+
+use super::{
+    error::NotifyError, Notification, NotificationKind, Outcome, ServiceChange, ServiceImage,
+};
+
+const NAME: &str = "message";
+
+#[derive(Serialize)]
+struct TemplateContext<'a> {
+    #[serde(flatten)]
+    notification: &'a Notification,
+    duration: i64,
+    summary: String,
+}
+
+/// A compiled template
+pub struct Template {
+    tera: Tera,
+}
+
+impl Template {
+    /// Compile the template, checking it renders an example notification of
+    /// every kind
+    pub fn new(source: &str) -> Result<Self, NotifyError> {
+        let mut tera = Tera::default();
+        tera.add_raw_template(NAME, source)?;
+        let template = Self { tera };
+        for example in examples() {
+            template.render(&example).map_err(|e| match e {
+                NotifyError::TemplateError(message) => NotifyError::TemplateError(format!(
+                    "{} notification: {}",
+                    example.kind.as_str(),
+                    message
+                )),
+                e => e,
+            })?;
+        }
+        Ok(template)
+    }
+
+    pub fn render(&self, notification: &Notification) -> Result<String, NotifyError> {
+        let context = Context::from_serialize(TemplateContext {
+            notification,
+            duration: (notification.finished_at - notification.started_at).num_seconds(),
+            summary: notification.summary(),
+        })?;
+        Ok(self.tera.render(NAME, &context)?)
+    }
+}
+
+/// Notifications of every kind with every field set, each with the body of
+/// its request, used to validate the templates
+fn examples() -> Vec<Notification> {
+    let update = json!({ "image": "shop/api", "tag": "2.0", "service": "shop_api" });
+    let release = json!({
+        "stack": "shop",
+        "images": [{ "image": "shop/api", "tag": "2.0" }],
+        "order": []
+    });
+    // a rollback is requested for a service, or done by a failed release
+    let rollback = json!({ "service": "shop_api" });
+    [
+        (NotificationKind::Update, update),
+        (NotificationKind::Release, release.clone()),
+        (NotificationKind::Rollback, rollback),
+        (NotificationKind::Rollback, release),
+    ]
+    .into_iter()
+    .map(|(kind, request)| {
+        Notification::new(kind, &Uuid::new_v4(), "github", Utc::now())
+            .with_outcome(Outcome::Success)
+            .with_request(&request)
+            .with_changes(vec![ServiceChange {
+                service: "shop_api".into(),
+                stack: Some("shop".into()),
+                old: ServiceImage::new("shop/api", "1.0"),
+                new: ServiceImage::new("shop/api", "2.0"),
+                error: Some("error".into()),
+            }])
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::notify::tests::notification;
+
+    #[test]
+    fn test_render() {
+        let template = Template::new(
+            "{{ actor }} {{ kind }} {% for change in changes %}{{ change.service }}:{{ change.new.tag }} {% endfor %}({{ outcome }})",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&notification()).unwrap(),
+            "github release shop_api:2.0 blog_web:1.27 (success)"
+        );
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        assert!(Template::new("{{ actor ").is_err());
+        assert!(Template::new("{{ unknown_variable }}").is_err());
+    }
+
+    #[test]
+    fn test_template_of_every_kind() {
+        // releases and rollbacks have no tag in their request
+        let error = Template::new("{{ actor }} deployed {{ request.tag }}")
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("release notification"));
+        assert!(
+            Template::new("{{ actor }} deployed {{ request.tag | default(value=\"\") }}").is_ok()
+        );
+    }
+}
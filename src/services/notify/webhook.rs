@@ -0,0 +1,80 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use reqwest::header::CONTENT_TYPE;
+
+use super::{error::NotifyError, Notification, Notifier};
+
+/// Post the notification as json to an url
+///
+/// With a template, the rendered template is posted instead, as json unless
+/// a `Content-Type` header is configured.
+pub struct WebhookNotifier {
+    url: String,
+    headers: HashMap<String, String>,
+    client: reqwest::Client,
+}
+
+impl WebhookNotifier {
+    pub fn new(url: &str, headers: HashMap<String, String>) -> Self {
+        Self {
+            url: url.to_owned(),
+            headers,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    async fn notify(
+        &self,
+        notification: &Notification,
+        message: Option<&str>,
+    ) -> Result<(), NotifyError> {
+        let mut request = match message {
+            Some(message) => {
+                let request = self.client.post(&self.url).body(message.to_owned());
+                if self
+                    .headers
+                    .keys()
+                    .any(|name| name.eq_ignore_ascii_case("content-type"))
+                {
+                    request
+                } else {
+                    request.header(CONTENT_TYPE, "application/json")
+                }
+            }
+            None => self.client.post(&self.url).json(notification),
+        };
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(NotifyError::Rejected(response.text().await?));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::notify::tests::{notification, stand_in};
+
+    #[tokio::test]
+    async fn test_webhook() {
+        let (url, received) = stand_in().await;
+        let notifier = WebhookNotifier::new(
+            &format!("{}/hooks/deploy", url),
+            HashMap::from([("X-Secret".to_owned(), "s3cr3t".to_owned())]),
+        );
+        notifier.notify(&notification(), None).await.unwrap();
+        let received = received.lock().unwrap();
+        assert_eq!(received[0].path, "/hooks/deploy");
+        assert_eq!(received[0].headers.get("X-Secret").unwrap(), "s3cr3t");
+        assert_eq!(received[0].body["outcome"], "success");
+        assert_eq!(received[0].body["changes"][0]["new"]["tag"], "2.0");
+    }
+}
@@ -0,0 +1,179 @@
+//! # Pre-pull of the images
+//!
+//! Before switching a service to a new image, a `GlobalJob` service with the
+//! new image runs on every node the service can be scheduled on (the same
+//! placement constraints and platforms). Swarm pulls the image on each node to
+//! create the job containers, so once every task has a container, the image is
+//! present everywhere and the update doesn't wait for the pulls.
+//!
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant};
+use tracing::info;
+use uuid::Uuid;
+
+use super::{
+    docker::{
+        error::DockerError,
+        types::{
+            Service, ServiceSpec, ServiceSpecMode, ServiceSpecModeGlobalJob,
+            ServiceTaskTemplateRestartPolicy, Task,
+        },
+        Docker,
+    },
+    jobs::JOB_LABEL,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Build the spec of the pre-pull job named `name`, running `image` where
+/// `service` can run
+///
+/// Only the placement is kept: the job doesn't need the networks, mounts,
+/// configs or secrets of the service, and its command exits at once.
+pub fn prepull_spec(service: &Service, name: &str, image: &str) -> ServiceSpec {
+    let mut spec = service.spec.clone();
+    spec.name = name.to_owned();
+    spec.labels = Some([(JOB_LABEL.to_owned(), service.spec.name.clone())].into());
+    spec.mode = Some(ServiceSpecMode {
+        global_job: Some(ServiceSpecModeGlobalJob {}),
+        ..Default::default()
+    });
+    spec.endpoint_spec = None;
+    spec.update_config = None;
+    spec.rollback_config = None;
+    let task_template = &mut spec.task_template;
+    task_template.networks = None;
+    task_template.resources = None;
+    task_template.restart_policy = Some(ServiceTaskTemplateRestartPolicy {
+        condition: Some("none".into()),
+        ..Default::default()
+    });
+    let container_spec = &mut task_template.container_spec;
+    container_spec.image = image.to_owned();
+    container_spec.labels = None;
+    container_spec.command = Some(vec!["true".into()]);
+    container_spec.args = None;
+    container_spec.env = None;
+    container_spec.mounts = None;
+    container_spec.configs = None;
+    container_spec.secrets = None;
+    container_spec.health_check = None;
+    spec
+}
+
+/// Pull `image` on the nodes of `service`, waiting up to `timeout`
+///
+/// `registry_auth` is the `X-Registry-Auth` of the private registries. The
+/// pre-pull job has a name of its own, so concurrent updates of the service
+/// don't collide, and is always removed, also when the future is dropped.
+pub async fn run(
+    docker: &Docker,
+    registry_auth: Option<&str>,
+    service: &Service,
+    image: &str,
+    timeout: Duration,
+) -> Result<(), DockerError> {
+    let name = format!(
+        "{}-prepull-{}",
+        service.spec.name,
+        &Uuid::new_v4().simple().to_string()[..8]
+    );
+    let spec = prepull_spec(service, &name, image);
+    let job = docker
+        .service_create_temporary(&spec, registry_auth)
+        .await?;
+    info!("Pulling {} on the nodes of {}", image, service.spec.name);
+    let result = wait_pulled(docker, &job.id, timeout).await;
+    job.remove().await;
+    result
+}
+
+/// The image is on the node of the task once its container is created
+fn pulled(task: &Task) -> bool {
+    task.status
+        .container_status
+        .as_ref()
+        .is_some_and(|status| status.container_id.is_some())
+}
+
+async fn wait_pulled(docker: &Docker, id: &str, timeout: Duration) -> Result<(), DockerError> {
+    let started_at = Instant::now();
+    loop {
+        let desired = docker.service_status(id).await?.desired_tasks;
+        let tasks = docker.tasks_list(Some(id)).await?;
+        if let Some(task) = tasks
+            .iter()
+            .find(|task| task.status.state == "rejected" && !pulled(task))
+        {
+            return Err(DockerError::JobError(format!(
+                "Pull failed on node {}: {}",
+                task.node_id.as_deref().unwrap_or_default(),
+                task.status.err.as_deref().unwrap_or_default()
+            )));
+        }
+        let pulled = tasks.iter().filter(|task| pulled(task)).count() as u64;
+        if desired > 0 && pulled >= desired {
+            info!("Image pulled on {} nodes", pulled);
+            return Ok(());
+        }
+        if started_at.elapsed() >= timeout {
+            return Err(DockerError::JobError(format!(
+                "Image pulled on {} of {} nodes after {}s",
+                pulled,
+                desired,
+                timeout.as_secs()
+            )));
+        }
+        sleep(POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_prepull_spec() {
+        let service: Service = serde_json::from_value(json!({
+            "ID": "abc",
+            "Version": { "Index": 10 },
+            "CreatedAt": "2024-06-01T10:00:00Z",
+            "UpdatedAt": "2024-06-01T10:00:00Z",
+            "Spec": {
+                "Name": "shop_api",
+                "Labels": { "com.docker.stack.namespace": "shop" },
+                "TaskTemplate": {
+                    "ContainerSpec": {
+                        "Image": "shop/api:1.0",
+                        "Args": ["serve"],
+                        "Env": ["DATABASE_URL=postgres://db"],
+                        "Secrets": [{ "SecretID": "s1", "SecretName": "db_password" }]
+                    },
+                    "Placement": { "Constraints": ["node.labels.zone == eu"] },
+                    "Networks": [{ "Target": "backend" }]
+                },
+                "Mode": { "Replicated": { "Replicas": 3 } }
+            }
+        }))
+        .unwrap();
+        let spec = prepull_spec(&service, "shop_api-prepull", "shop/api:2.0");
+        let value = serde_json::to_value(&spec).unwrap();
+        assert_eq!(value["Name"], "shop_api-prepull");
+        assert_eq!(value["Mode"]["GlobalJob"], json!({}));
+        assert!(value["Mode"]["Replicated"].is_null());
+        assert_eq!(value["Labels"], json!({ JOB_LABEL: "shop_api" }));
+        assert_eq!(
+            value["TaskTemplate"]["Placement"]["Constraints"][0],
+            "node.labels.zone == eu"
+        );
+        assert!(value["TaskTemplate"]["Networks"].is_null());
+        let container_spec = &value["TaskTemplate"]["ContainerSpec"];
+        assert_eq!(container_spec["Image"], "shop/api:2.0");
+        assert_eq!(container_spec["Command"], json!(["true"]));
+        assert!(container_spec["Args"].is_null());
+        assert!(container_spec["Env"].is_null());
+        assert!(container_spec["Secrets"].is_null());
+    }
+}
@@ -0,0 +1,206 @@
+//! # Secrets and redaction
+//!
+//! The passwords and tokens of the configuration are kept as [Secret], hidden
+//! from the debug output.
+//!
+//! The environment variables of the services often hold credentials: the
+//! [Redactor] hides the values of the variables whose name matches one of the
+//! `redact_env` patterns (case insensitive, anywhere in the name) before the
+//! services and the requests are logged, notified or returned by the api.
+//!
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::docker::types::{KeyValueChanges, Service, ServiceUpdateOptions};
+
+/// Replaces the hidden values
+pub const REDACTED: &str = "***";
+
+/// A password or a token, hidden from the debug output
+///
+/// The value is serialized as is: the configuration is compared as json
+/// when reloaded.
+#[derive(Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    /// The value, to send it where it is needed
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self(value.to_owned())
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+/// Hide the values of the sensitive environment variables
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    /// Uppercase patterns
+    patterns: Vec<String>,
+}
+
+impl Redactor {
+    pub fn new(patterns: &[String]) -> Self {
+        Self {
+            patterns: patterns
+                .iter()
+                .filter(|pattern| !pattern.is_empty())
+                .map(|pattern| pattern.to_uppercase())
+                .collect(),
+        }
+    }
+
+    /// The name of the variable matches a pattern
+    pub fn is_sensitive(&self, name: &str) -> bool {
+        let name = name.to_uppercase();
+        self.patterns
+            .iter()
+            .any(|pattern| name.contains(pattern.as_str()))
+    }
+
+    /// Variables of a container spec, as `NAME=value`
+    pub fn env(&self, env: &mut [String]) {
+        for variable in env.iter_mut() {
+            if let Some((name, _)) = variable.split_once('=') {
+                if self.is_sensitive(name) {
+                    *variable = format!("{}={}", name, REDACTED);
+                }
+            }
+        }
+    }
+
+    /// Variables set by a request
+    pub fn changes(&self, changes: &mut KeyValueChanges) {
+        for (name, value) in changes.set.iter_mut() {
+            if self.is_sensitive(name) {
+                *value = REDACTED.to_owned();
+            }
+        }
+    }
+
+    pub fn options(&self, options: &mut ServiceUpdateOptions) {
+        if let Some(env) = options.env.as_mut() {
+            self.changes(env);
+        }
+    }
+
+    /// The current and the previous spec of the service
+    pub fn service(&self, service: &mut Service) {
+        for spec in [Some(&mut service.spec), service.previous_spec.as_mut()]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(env) = spec.task_template.container_spec.env.as_mut() {
+                self.env(env);
+            }
+        }
+    }
+
+    /// A serialized request or spec: every `env` (or `Env`) object of changes
+    /// or list of variables
+    pub fn value(&self, value: &mut Value) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object.iter_mut() {
+                    if key.eq_ignore_ascii_case("env") {
+                        self.value_env(value);
+                    } else {
+                        self.value(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.value(value)),
+            _ => {}
+        }
+    }
+
+    fn value_env(&self, env: &mut Value) {
+        match env {
+            Value::Object(changes) => {
+                if let Some(Value::Object(set)) = changes.get_mut("set") {
+                    for (name, value) in set.iter_mut() {
+                        if self.is_sensitive(name) {
+                            *value = Value::from(REDACTED);
+                        }
+                    }
+                }
+            }
+            Value::Array(variables) => {
+                for variable in variables.iter_mut() {
+                    let Some((name, _)) = variable.as_str().and_then(|v| v.split_once('=')) else {
+                        continue;
+                    };
+                    if self.is_sensitive(name) {
+                        *variable = Value::from(format!("{}={}", name, REDACTED));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_secret() {
+        let secret = Secret::from("s3cr3t");
+        assert_eq!(format!("{:?}", secret), REDACTED);
+        assert_eq!(secret.expose(), "s3cr3t");
+        assert_eq!(serde_json::to_value(&secret).unwrap(), json!("s3cr3t"));
+    }
+
+    #[test]
+    fn test_redactor() {
+        let redactor = Redactor::new(&["password".into(), "TOKEN".into()]);
+        assert!(redactor.is_sensitive("DB_PASSWORD"));
+        assert!(redactor.is_sensitive("github_token"));
+        assert!(!redactor.is_sensitive("DB_HOST"));
+
+        let mut env = vec!["DB_PASSWORD=s3cr3t".to_owned(), "DB_HOST=db".to_owned()];
+        redactor.env(&mut env);
+        assert_eq!(env, vec!["DB_PASSWORD=***", "DB_HOST=db"]);
+
+        let mut request = json!({
+            "image": "shop/api",
+            "env": { "set": { "API_TOKEN": "s3cr3t", "LEVEL": "debug" }, "unset": ["DB_PASSWORD"] },
+            "change": { "options": { "env": { "set": { "DB_PASSWORD": "s3cr3t" } } } },
+            "Spec": { "TaskTemplate": { "ContainerSpec": { "Env": ["API_TOKEN=s3cr3t", "LEVEL=debug"] } } }
+        });
+        redactor.value(&mut request);
+        assert_eq!(
+            request["env"],
+            json!({ "set": { "API_TOKEN": "***", "LEVEL": "debug" }, "unset": ["DB_PASSWORD"] })
+        );
+        assert_eq!(
+            request["change"]["options"]["env"]["set"]["DB_PASSWORD"],
+            "***"
+        );
+        assert_eq!(
+            request["Spec"]["TaskTemplate"]["ContainerSpec"]["Env"],
+            json!(["API_TOKEN=***", "LEVEL=debug"])
+        );
+    }
+}
@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("Registry API error: {0}")]
+    RegistryAPIError(#[from] reqwest::Error),
+    #[error("Authentication error: {0}")]
+    AuthenticationError(String),
+    #[error("Manifest not found: {0}")]
+    ManifestNotFound(String),
+    #[error("Missing digest for {0}")]
+    MissingDigest(String),
+    #[error("Registry unavailable: {0}")]
+    Unavailable(String),
+}
@@ -0,0 +1,393 @@
+pub mod error;
+
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::URL_SAFE, Engine};
+use error::RegistryError;
+use reqwest::{header, Response, StatusCode, Url};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::instrument;
+
+use super::redact::Secret;
+
+const DOCKER_HUB: &str = "registry-1.docker.io";
+const DOCKER_HUB_ALIASES: [&str; 3] = ["docker.io", "index.docker.io", DOCKER_HUB];
+const MANIFEST_TYPES: [&str; 4] = [
+    "application/vnd.docker.distribution.manifest.list.v2+json",
+    "application/vnd.oci.image.index.v1+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+    "application/vnd.oci.image.manifest.v1+json",
+];
+
+/// Credentials to authenticate with a registry
+#[derive(Debug, Clone)]
+pub struct RegistryCredential {
+    pub url: String,
+    pub username: String,
+    pub password: Secret,
+}
+
+/// Reference of an image (without tag) in a registry
+#[derive(Debug, PartialEq)]
+pub struct ImageReference {
+    pub host: String,
+    pub repository: String,
+}
+
+impl ImageReference {
+    /// Parse an image name like `nginx`, `user/app` or `registry.io:5000/team/app`
+    pub fn parse(image: &str) -> Self {
+        match image.split_once('/') {
+            Some((host, repository))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                Self {
+                    host: host.to_owned(),
+                    repository: repository.to_owned(),
+                }
+            }
+            Some(_) => Self {
+                host: DOCKER_HUB.to_owned(),
+                repository: image.to_owned(),
+            },
+            None => Self {
+                host: DOCKER_HUB.to_owned(),
+                repository: format!("library/{}", image),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Docker Registry API (v2)
+///
+/// Implement the basic services to interact with the registries
+/// - resolve_digest: Get the digest of an image tag
+/// - ping: Check a registry is reachable
+///
+pub struct Registry {
+    credentials: Vec<RegistryCredential>,
+    client: reqwest::Client,
+}
+
+impl Registry {
+    /// Create a new Registry instance
+    ///
+    /// # Arguments
+    /// * `credentials` - The credentials of the known registries
+    ///
+    pub fn new(credentials: Vec<RegistryCredential>) -> Self {
+        Registry {
+            credentials,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Get the digest of the manifest of `image:tag`
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let registry = Registry::new(vec![]);
+    /// let digest = registry.resolve_digest("nginx", "1.27").await.unwrap();
+    /// ```
+    #[instrument(skip(self))]
+    pub async fn resolve_digest(&self, image: &str, tag: &str) -> Result<String, RegistryError> {
+        let reference = ImageReference::parse(image);
+        let credential = self.credential(&reference.host);
+        let url = format!(
+            "{}/v2/{}/manifests/{}",
+            self.base_url(&reference.host, credential),
+            reference.repository,
+            tag
+        );
+        let mut response = self.head_manifest(&url, None, None).await?;
+        if response.status() == StatusCode::UNAUTHORIZED {
+            let challenge = response
+                .headers()
+                .get(header::WWW_AUTHENTICATE)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+                .to_owned();
+            response = if challenge.starts_with("Basic") {
+                self.head_manifest(&url, None, credential).await?
+            } else {
+                let token = self.token(&challenge, credential).await?;
+                self.head_manifest(&url, Some(&token), None).await?
+            };
+        }
+        match response.status() {
+            StatusCode::NOT_FOUND => Err(RegistryError::ManifestNotFound(format!(
+                "{}:{}",
+                image, tag
+            ))),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(
+                RegistryError::AuthenticationError(format!("Access denied to {}", image)),
+            ),
+            _ => {
+                let response = response.error_for_status()?;
+                response
+                    .headers()
+                    .get("Docker-Content-Digest")
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_owned())
+                    .ok_or_else(|| RegistryError::MissingDigest(format!("{}:{}", image, tag)))
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(url = %url))]
+    async fn head_manifest(
+        &self,
+        url: &str,
+        token: Option<&str>,
+        credential: Option<&RegistryCredential>,
+    ) -> Result<Response, RegistryError> {
+        let mut request = self
+            .client
+            .head(url)
+            .header(header::ACCEPT, MANIFEST_TYPES.join(", "));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        } else if let Some(credential) = credential {
+            request = request.basic_auth(&credential.username, Some(credential.password.expose()));
+        }
+        Ok(request.send().await?)
+    }
+
+    /// Request a bearer token following the `WWW-Authenticate` challenge
+    #[instrument(skip_all)]
+    async fn token(
+        &self,
+        challenge: &str,
+        credential: Option<&RegistryCredential>,
+    ) -> Result<String, RegistryError> {
+        let params = parse_challenge(challenge);
+        let realm = params.get("realm").ok_or_else(|| {
+            RegistryError::AuthenticationError(format!("Unsupported challenge: {}", challenge))
+        })?;
+        let query = params
+            .iter()
+            .filter(|(key, _)| key.as_str() != "realm")
+            .collect::<Vec<_>>();
+        let mut request = self.client.get(realm).query(&query);
+        if let Some(credential) = credential {
+            request = request.basic_auth(&credential.username, Some(credential.password.expose()));
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(RegistryError::AuthenticationError(format!(
+                "Token request failed: {}",
+                response.status()
+            )));
+        }
+        let token = response.json::<TokenResponse>().await?;
+        token
+            .token
+            .or(token.access_token)
+            .ok_or_else(|| RegistryError::AuthenticationError("Empty token".into()))
+    }
+
+    /// Value of the `X-Registry-Auth` header letting the swarm nodes pull the
+    /// image (with or without tag), when the registry has credentials
+    pub fn docker_auth(&self, image: &str) -> Option<String> {
+        let reference = ImageReference::parse(image);
+        let credential = self.credential(&reference.host)?;
+        let auth = json!({
+            "username": credential.username,
+            "password": credential.password.expose(),
+            "serveraddress": reference.host,
+        });
+        Some(URL_SAFE.encode(auth.to_string()))
+    }
+
+    /// Check the registry answers its api, with or without authentication
+    #[instrument(skip(self))]
+    pub async fn ping(&self, url: &str) -> Result<(), RegistryError> {
+        let host = registry_host(url)
+            .ok_or_else(|| RegistryError::Unavailable(format!("invalid url {}", url)))?;
+        let url = format!("{}/v2/", self.base_url(&host, self.credential(&host)));
+        let response = self.client.get(&url).send().await?;
+        match response.status() {
+            status if status.is_success() || status == StatusCode::UNAUTHORIZED => Ok(()),
+            status => Err(RegistryError::Unavailable(format!(
+                "{} returned {}",
+                url, status
+            ))),
+        }
+    }
+
+    fn credential(&self, host: &str) -> Option<&RegistryCredential> {
+        self.credentials.iter().find(|credential| {
+            let Some(credential_host) = registry_host(&credential.url) else {
+                return false;
+            };
+            credential_host == host
+                || (DOCKER_HUB_ALIASES.contains(&host)
+                    && DOCKER_HUB_ALIASES.contains(&credential_host.as_str()))
+        })
+    }
+
+    fn base_url(&self, host: &str, credential: Option<&RegistryCredential>) -> String {
+        let scheme = credential
+            .and_then(|credential| Url::parse(&credential.url).ok())
+            .map(|url| url.scheme().to_owned())
+            .unwrap_or_else(|| "https".to_owned());
+        format!("{}://{}", scheme, host)
+    }
+}
+
+/// Get the `host[:port]` of a registry url, the scheme is optional
+fn registry_host(url: &str) -> Option<String> {
+    let url = if url.contains("://") {
+        Url::parse(url).ok()?
+    } else {
+        Url::parse(&format!("https://{}", url)).ok()?
+    };
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    })
+}
+
+/// Parse the parameters of a `Bearer realm="...",service="..."` challenge
+fn parse_challenge(challenge: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = challenge
+        .split_once(' ')
+        .map_or("", |(_, params)| params)
+        .trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_owned();
+        let (value, remaining) = match value.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        params.insert(key, value.to_owned());
+        rest = remaining;
+    }
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::{get, head},
+        Json, Router,
+    };
+
+    #[test]
+    fn test_image_reference() {
+        assert_eq!(
+            ImageReference::parse("nginx"),
+            ImageReference {
+                host: DOCKER_HUB.into(),
+                repository: "library/nginx".into()
+            }
+        );
+        assert_eq!(
+            ImageReference::parse("grafana/grafana"),
+            ImageReference {
+                host: DOCKER_HUB.into(),
+                repository: "grafana/grafana".into()
+            }
+        );
+        assert_eq!(
+            ImageReference::parse("registry.usign.io:5000/team/app"),
+            ImageReference {
+                host: "registry.usign.io:5000".into(),
+                repository: "team/app".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_docker_auth() {
+        let registry = Registry::new(vec![RegistryCredential {
+            url: "https://registry.usign.io".into(),
+            username: "servers".into(),
+            password: "secret".into(),
+        }]);
+        assert!(registry.docker_auth("nginx").is_none());
+        let auth = registry.docker_auth("registry.usign.io/team/app").unwrap();
+        let auth: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE.decode(auth).unwrap()).unwrap();
+        assert_eq!(auth["username"], "servers");
+        assert_eq!(auth["serveraddress"], "registry.usign.io");
+    }
+
+    #[test]
+    fn test_parse_challenge() {
+        let params = parse_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/nginx:pull,push""#,
+        );
+        assert_eq!(params.get("realm").unwrap(), "https://auth.docker.io/token");
+        assert_eq!(params.get("service").unwrap(), "registry.docker.io");
+        assert_eq!(
+            params.get("scope").unwrap(),
+            "repository:library/nginx:pull,push"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolve_digest_with_token() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new()
+            .route(
+                "/v2/team/app/manifests/1.0",
+                head(move |headers: HeaderMap| async move {
+                    let authorization = headers.get("Authorization").map(|v| v.to_str().unwrap());
+                    if authorization == Some("Bearer secret-token") {
+                        [("Docker-Content-Digest", "sha256:1234")].into_response()
+                    } else {
+                        (
+                            StatusCode::UNAUTHORIZED,
+                            [(
+                                "WWW-Authenticate",
+                                format!(r#"Bearer realm="http://{}/token",service="test""#, addr),
+                            )],
+                        )
+                            .into_response()
+                    }
+                }),
+            )
+            .route(
+                "/token",
+                get(|headers: HeaderMap| async move {
+                    // basic auth of servers:secret
+                    if headers.get("Authorization").unwrap() == "Basic c2VydmVyczpzZWNyZXQ=" {
+                        Json(serde_json::json!({ "token": "secret-token" })).into_response()
+                    } else {
+                        StatusCode::UNAUTHORIZED.into_response()
+                    }
+                }),
+            );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let registry = Registry::new(vec![RegistryCredential {
+            url: format!("http://{}", addr),
+            username: "servers".into(),
+            password: "secret".into(),
+        }]);
+        let image = format!("{}/team/app", addr);
+        assert_eq!(
+            registry.resolve_digest(&image, "1.0").await.unwrap(),
+            "sha256:1234"
+        );
+        assert!(matches!(
+            registry.resolve_digest(&image, "2.0").await,
+            Err(RegistryError::ManifestNotFound(_))
+        ));
+    }
+}
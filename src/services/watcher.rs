@@ -0,0 +1,194 @@
+//! # Watcher of the docker service events
+//!
+//! While the events are followed, the services cache of [Docker] is enabled
+//! and invalidated by each event. Changes not done by the updater are recorded
+//! in the [History] as external changes: the updater registers its changes
+//! before calling docker (see [History::expect]) and docker remembers the
+//! services it created, like canaries and jobs.
+//!
+use std::time::Duration;
+
+use chrono::Utc;
+use futures::StreamExt;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use super::{
+    canary::CANARY_LABEL,
+    docker::{events::DockerEvent, types::Service, Docker},
+    history::{History, HistoryAction, HistoryEntry, EXTERNAL_ACTOR},
+    jobs::JOB_LABEL,
+};
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Time an update done by the updater takes to be reported by docker
+const UPDATE_WINDOW: chrono::Duration = chrono::Duration::minutes(10);
+
+/// Follow the docker service events forever, reconnecting on errors
+pub async fn watch(docker: &Docker, history: &History) {
+    loop {
+        match docker.events("service").await {
+            Ok(events) => {
+                info!("Following docker service events");
+                docker.cache_enable(true);
+                let mut events = std::pin::pin!(events);
+                while let Some(event) = events.next().await {
+                    match event {
+                        Ok(event) => handle(docker, history, &event).await,
+                        Err(e) => {
+                            warn!("Error reading docker events: {}", e);
+                            break;
+                        }
+                    }
+                }
+                docker.cache_enable(false);
+            }
+            Err(e) => warn!("Error following docker events: {}", e),
+        }
+        sleep(RETRY_INTERVAL).await;
+    }
+}
+
+/// Services created by the updater itself, like canaries and jobs
+fn is_managed(service: &Service) -> bool {
+    service.label(CANARY_LABEL).is_some() || service.label(JOB_LABEL).is_some()
+}
+
+/// Event of a service created by the updater: its labels when docker reports
+/// them in the attributes, or its id remembered by [Docker::service_create]
+fn is_managed_event(docker: &Docker, event: &DockerEvent, removed: bool) -> bool {
+    event.attribute(CANARY_LABEL).is_some()
+        || event.attribute(JOB_LABEL).is_some()
+        || docker.created_by_us(&event.actor.id, removed)
+}
+
+async fn handle(docker: &Docker, history: &History, event: &DockerEvent) {
+    docker.cache_invalidate();
+    let id = event.actor.id.as_str();
+    let name = event.attribute("name").unwrap_or(id);
+    let entry = match event.action.as_str() {
+        "update" => {
+            // events without image change are about the progress of the rollout
+            let Some(image) = event.attribute("image.new") else {
+                return;
+            };
+            if history.updated_by_us(id, image, Utc::now() - UPDATE_WINDOW) {
+                return;
+            }
+            HistoryEntry::new(HistoryAction::Update, id, name, EXTERNAL_ACTOR)
+                .with_images(event.attribute("image.old"), Some(image))
+        }
+        "create" => {
+            if is_managed_event(docker, event, false) {
+                return;
+            }
+            let Ok(service) = docker.service_inspect(id).await else {
+                return;
+            };
+            if is_managed(&service) {
+                return;
+            }
+            HistoryEntry::new(HistoryAction::Create, id, name, EXTERNAL_ACTOR)
+                .with_images(None, Some(&service.spec.task_template.container_spec.image))
+        }
+        "remove" => {
+            if is_managed_event(docker, event, true) {
+                return;
+            }
+            let service = docker.cached_service(id);
+            if service.as_ref().is_some_and(is_managed) {
+                return;
+            }
+            let image = service
+                .as_ref()
+                .map(|service| service.spec.task_template.container_spec.image.as_str());
+            HistoryEntry::new(HistoryAction::Remove, id, name, EXTERNAL_ACTOR)
+                .with_images(image, None)
+        }
+        _ => return,
+    };
+    info!(
+        "External change of {}: {:?} {:?} -> {:?}",
+        name, entry.action, entry.from_image, entry.to_image
+    );
+    history.record(entry);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::docker::{types::ServiceSpec, DockerBuilder};
+    use axum::{http::StatusCode, routing::get, routing::post, Json, Router};
+    use serde_json::json;
+
+    /// Docker daemon creating a job service, whose events don't have labels
+    async fn stand_in() -> String {
+        let app = Router::new()
+            .route(
+                "/services/create",
+                post(|| async { Json(json!({ "ID": "job1" })) }),
+            )
+            .route("/services/:id", get(|| async { StatusCode::NOT_FOUND }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    fn event(action: &str, id: &str, attributes: serde_json::Value) -> DockerEvent {
+        serde_json::from_value(json!({
+            "Type": "service",
+            "Action": action,
+            "Actor": { "ID": id, "Attributes": attributes },
+            "time": 1717236000
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_managed_and_expected_changes() {
+        let docker = DockerBuilder::builder()
+            .with_http_url(&stand_in().await)
+            .build();
+        let history = History::new(10, None);
+        docker.cache_enable(true);
+        let spec: ServiceSpec = serde_json::from_value(json!({
+            "Name": "shop_api-job",
+            "TaskTemplate": { "ContainerSpec": { "Image": "shop/api:1.0" } }
+        }))
+        .unwrap();
+        let id = docker.service_create_with_auth(&spec, None).await.unwrap();
+
+        // removal of the job created by the updater
+        handle(
+            &docker,
+            &history,
+            &event("remove", &id, json!({ "name": "shop_api-job" })),
+        )
+        .await;
+        assert!(history.list(None).is_empty());
+
+        // update reported before the updater records it
+        let expected = history.expect("web1", "nginx:1.27");
+        let update = event(
+            "update",
+            "web1",
+            json!({ "name": "web", "image.new": "nginx:1.27@sha256:1" }),
+        );
+        handle(&docker, &history, &update).await;
+        assert!(history.list(None).is_empty());
+        drop(expected);
+
+        handle(
+            &docker,
+            &history,
+            &event("remove", "web1", json!({ "name": "web" })),
+        )
+        .await;
+        let entries = history.list(None);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].action, HistoryAction::Remove);
+        assert!(entries[0].external);
+    }
+}
@@ -0,0 +1,156 @@
+//! # OpenTelemetry traces
+//!
+//! With the `otel` feature (enabled by default) and an `otel_endpoint`, the
+//! spans are exported to an OTLP/HTTP collector: the `request` span of each
+//! http request (see [request](crate::controllers::request)), with a span for
+//! each call to the docker daemon and to the registries.
+//!
+//! A request with a W3C `traceparent` header, like a webhook of an
+//! instrumented CI, continues its trace.
+//!
+//! ```json
+//! "otel_endpoint": "http://otel-collector:4318",
+//! "otel_service_name": "updater"
+//! ```
+//!
+use std::sync::OnceLock;
+
+use axum::http::HeaderMap;
+use opentelemetry::{
+    global,
+    propagation::Extractor,
+    trace::{TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator, runtime, trace::TracerProvider, Resource,
+};
+use tracing::{Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::Config;
+
+/// Provider of the server, flushed by [shutdown]
+static PROVIDER: OnceLock<TracerProvider> = OnceLock::new();
+
+/// Build the provider exporting the spans to the collector
+pub fn provider(endpoint: &str, service_name: &str) -> Result<TracerProvider, TraceError> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()?;
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            service_name.to_owned(),
+        )]))
+        .build())
+}
+
+/// The layer exporting the spans, when the config has an `otel_endpoint`
+pub fn layer<S>(config: &Config) -> Option<OpenTelemetryLayer<S, opentelemetry_sdk::trace::Tracer>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let endpoint = config.otel_endpoint.as_deref()?;
+    let provider = match provider(endpoint, &config.otel_service_name) {
+        Ok(provider) => provider,
+        Err(e) => {
+            eprintln!("Error building the OpenTelemetry exporter: {}", e);
+            return None;
+        }
+    };
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer = provider.tracer("updater");
+    PROVIDER.get_or_init(|| provider);
+    Some(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+/// Continue the trace of the `traceparent` header in the span
+pub fn set_parent(span: &Span, headers: &HeaderMap) {
+    let context =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(context);
+}
+
+/// Export the spans still in the batch
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(e) = provider.shutdown() {
+            eprintln!("Error exporting the last spans: {}", e);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Bytes, routing::post, Router};
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Local collector recording the bodies of the exported traces
+    async fn collector() -> (String, Arc<Mutex<Vec<Bytes>>>) {
+        let received = Arc::new(Mutex::new(vec![]));
+        let bodies = received.clone();
+        let app = Router::new().route(
+            "/v1/traces",
+            post(move |body: Bytes| async move {
+                bodies.lock().unwrap().push(body);
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (url, received)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_export() {
+        let (url, received) = collector().await;
+        let provider = provider(&url, "updater-test").unwrap();
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            set_parent(&span, &headers);
+            span.in_scope(|| tracing::info_span!("services_list").in_scope(|| {}));
+        });
+        provider.force_flush();
+
+        let received = received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let body = &received[0];
+        let contains = |needle: &[u8]| body.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(b"updater-test"));
+        assert!(contains(b"services_list"));
+        let trace_id = [
+            0x4b, 0xf9, 0x2f, 0x35, 0x77, 0xb3, 0x4d, 0xa6, 0xa3, 0xce, 0x92, 0x9d, 0x0e, 0x0e,
+            0x47, 0x36,
+        ];
+        assert!(contains(&trace_id));
+    }
+}